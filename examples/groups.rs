@@ -15,7 +15,7 @@ fn main() {
     let mut groups: Vec<Group> = get_user_groups(user.name(), user.primary_group_id())
         .expect("No user groups?");
 
-    groups.sort_by(|a, b| a.gid().cmp(&b.gid()));
+    groups.sort_by_key(|g| g.gid());
     for group in groups {
         println!("Group {} has name {}", group.gid(), group.name().to_string_lossy());
     }
@@ -23,7 +23,7 @@ fn main() {
     let mut groups = group_access_list()
         .expect("Group access list");
 
-    groups.sort_by(|a, b| a.gid().cmp(&b.gid()));
+    groups.sort_by_key(|g| g.gid());
     println!("\nGroup access list:");
     for group in groups {
         println!("Group {} has name {}", group.gid(), group.name().to_string_lossy());
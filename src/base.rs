@@ -35,12 +35,15 @@ use std::mem;
 use std::io;
 use std::os::unix::ffi::OsStrExt;
 use std::ptr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+use std::sync::atomic::{AtomicPtr, Ordering};
 
 #[cfg(feature = "logging")]
 extern crate log;
 #[cfg(feature = "logging")]
-use self::log::trace;
+use self::log::{debug, trace};
+#[cfg(feature = "logging")]
+use std::time::{Duration, Instant};
 
 use libc::{c_char, uid_t, gid_t, c_int};
 use libc::passwd as c_passwd;
@@ -106,7 +109,7 @@ impl User {
     /// assert_eq!(user.name(), OsStr::new("stevedore"));
     /// ```
     pub fn name(&self) -> &OsStr {
-        &*self.name_arc
+        &self.name_arc
     }
 
     /// Returns the ID of this user’s primary group.
@@ -143,6 +146,24 @@ impl User {
     pub fn groups(&self) -> Option<Vec<Group>> {
         get_user_groups(self.name(), self.primary_group_id())
     }
+
+    /// Returns roughly how many bytes this user’s fields take up on the heap.
+    #[cfg(feature = "cache")]
+    pub(crate) fn heap_size(&self) -> usize {
+        self.name_arc.len() + self.extras.heap_size()
+    }
+
+    /// Returns a copy of this user with only its IDs and name, sharing the
+    /// name rather than copying it.
+    #[cfg(feature = "cache")]
+    pub(crate) fn to_bare(&self) -> User {
+        User {
+            uid:           self.uid,
+            name_arc:      Arc::clone(&self.name_arc),
+            primary_group: self.primary_group,
+            extras:        os::UserExtras::empty(),
+        }
+    }
 }
 
 impl fmt::Debug for User {
@@ -220,7 +241,24 @@ impl Group {
     /// assert_eq!(group.name(), OsStr::new("database"));
     /// ```
     pub fn name(&self) -> &OsStr {
-        &*self.name_arc
+        &self.name_arc
+    }
+
+    /// Returns roughly how many bytes this group’s fields take up on the heap.
+    #[cfg(feature = "cache")]
+    pub(crate) fn heap_size(&self) -> usize {
+        self.name_arc.len() + self.extras.heap_size()
+    }
+
+    /// Returns a copy of this group with only its ID and name, sharing the
+    /// name rather than copying it.
+    #[cfg(feature = "cache")]
+    pub(crate) fn to_bare(&self) -> Group {
+        Group {
+            gid:      self.gid,
+            name_arc: Arc::clone(&self.name_arc),
+            extras:   os::GroupExtras::default(),
+        }
     }
 }
 
@@ -267,6 +305,17 @@ unsafe fn passwd_to_user(passwd: c_passwd) -> User {
     }
 }
 
+/// Reads only the IDs and name from the `c_passwd`, leaving every other field
+/// empty.
+unsafe fn passwd_to_bare_user(passwd: c_passwd) -> User {
+    User {
+        uid:           passwd.pw_uid,
+        name_arc:      from_raw_buf(passwd.pw_name),
+        primary_group: passwd.pw_gid,
+        extras:        os::UserExtras::empty(),
+    }
+}
+
 /// Reads data from the `c_group` and returns it as a `Group`.
 unsafe fn struct_to_group(group: c_group) -> Group {
     #[cfg(feature = "logging")]
@@ -281,6 +330,15 @@ unsafe fn struct_to_group(group: c_group) -> Group {
     }
 }
 
+/// Reads only the ID and name from the `c_group`, leaving the members empty.
+unsafe fn struct_to_bare_group(group: c_group) -> Group {
+    Group {
+        gid:      group.gr_gid,
+        name_arc: from_raw_buf(group.gr_name),
+        extras:   os::GroupExtras::default(),
+    }
+}
+
 /// Expand a list of group members to a vector of strings.
 ///
 /// The list of members is, in true C fashion, a pointer to a pointer of
@@ -306,6 +364,36 @@ unsafe fn members(groups: *mut *mut c_char) -> Vec<OsString> {
 }
 
 
+/// Lookups that take at least this long get logged at `Debug` level, rather
+/// than `Trace`, so that slow name service backends stand out.
+#[cfg(feature = "logging")]
+const SLOW_LOOKUP: Duration = Duration::from_millis(100);
+
+/// Logs how long a call to the given libc function took.
+#[cfg(feature = "logging")]
+fn log_elapsed(function: &str, started: Instant) {
+    let elapsed = started.elapsed();
+
+    if elapsed >= SLOW_LOOKUP {
+        debug!("Slow call: {} took {:?}", function, elapsed);
+    }
+    else {
+        trace!("{} took {:?}", function, elapsed);
+    }
+}
+
+/// Returns the error reported by one of the `_r` lookup functions, if any.
+///
+/// Some systems report that there is no such entry by returning an error
+/// number, rather than zero with a null result, so the ones POSIX lists for
+/// this don’t count as errors.
+fn lookup_error(r: c_int) -> Option<io::Error> {
+    match r {
+        0 | libc::ENOENT | libc::ESRCH | libc::EBADF | libc::EPERM => None,
+        e => Some(io::Error::from_raw_os_error(e)),
+    }
+}
+
 /// Searches for a `User` with the given ID in the system’s user database.
 /// Returns it if one is found, otherwise returns `None`.
 ///
@@ -324,39 +412,60 @@ unsafe fn members(groups: *mut *mut c_char) -> Vec<OsString> {
 /// }
 /// ```
 pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
+    try_get_user_by_uid(uid).unwrap_or_default()
+}
+
+/// Searches for a `User` with the given ID, like `get_user_by_uid`, but
+/// returns `Err` if the C library reports an error, rather than treating it
+/// as the user not existing.
+pub(crate) fn try_get_user_by_uid(uid: uid_t) -> io::Result<Option<User>> {
+    lookup_user_by_uid(uid, passwd_to_user)
+}
+
+/// Looks up the user with the given ID, converting the C library’s result
+/// with the given function.
+fn lookup_user_by_uid<T>(uid: uid_t, convert: unsafe fn(c_passwd) -> T) -> io::Result<Option<T>> {
     let mut passwd = unsafe { mem::zeroed::<c_passwd>() };
     let mut buf = vec![0; 2048];
     let mut result = ptr::null_mut::<c_passwd>();
 
     #[cfg(feature = "logging")]
     trace!("Running getpwuid_r for user #{}", uid);
+    #[cfg(feature = "logging")]
+    let started = Instant::now();
 
-    loop {
+    let r = loop {
         let r = unsafe {
             libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
         };
 
         if r != libc::ERANGE {
-            break;
+            break r;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
+        let newsize = buf.len().checked_mul(2).ok_or_else(|| io::Error::from_raw_os_error(libc::ERANGE))?;
         buf.resize(newsize, 0);
+    };
+
+    #[cfg(feature = "logging")]
+    log_elapsed("getpwuid_r", started);
+
+    if let Some(e) = lookup_error(r) {
+        return Err(e);
     }
 
     if result.is_null() {
-        // There is no such user, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such user.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getpwuid_r should be its input passwd.
-        return None;
+        return Ok(None);
     }
 
-    let user = unsafe { passwd_to_user(result.read()) };
-    Some(user)
+    let user = unsafe { convert(result.read()) };
+    Ok(Some(user))
 }
 
 /// Searches for a `User` with the given username in the system’s user database.
@@ -377,12 +486,24 @@ pub fn get_user_by_uid(uid: uid_t) -> Option<User> {
 /// }
 /// ```
 pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User> {
-    let username = match CString::new(username.as_ref().as_bytes()) {
+    try_get_user_by_name(username).unwrap_or_default()
+}
+
+/// Searches for a `User` with the given username, like `get_user_by_name`,
+/// but returns `Err` if the C library reports an error.
+pub(crate) fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> io::Result<Option<User>> {
+    lookup_user_by_name(username.as_ref(), passwd_to_user)
+}
+
+/// Looks up the user with the given name, converting the C library’s result
+/// with the given function.
+fn lookup_user_by_name<T>(username: &OsStr, convert: unsafe fn(c_passwd) -> T) -> io::Result<Option<T>> {
+    let username = match CString::new(username.as_bytes()) {
         Ok(u)  => u,
         Err(_) => {
             // The username that was passed in contained a null character,
             // which will match no usernames.
-            return None;
+            return Ok(None);
         }
     };
 
@@ -392,33 +513,41 @@ pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User>
 
     #[cfg(feature = "logging")]
     trace!("Running getpwnam_r for user {:?}", username.as_ref());
+    #[cfg(feature = "logging")]
+    let started = Instant::now();
 
-    loop {
+    let r = loop {
         let r = unsafe {
             libc::getpwnam_r(username.as_ptr(), &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
         };
 
         if r != libc::ERANGE {
-            break;
+            break r;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
+        let newsize = buf.len().checked_mul(2).ok_or_else(|| io::Error::from_raw_os_error(libc::ERANGE))?;
         buf.resize(newsize, 0);
+    };
+
+    #[cfg(feature = "logging")]
+    log_elapsed("getpwnam_r", started);
+
+    if let Some(e) = lookup_error(r) {
+        return Err(e);
     }
 
     if result.is_null() {
-        // There is no such user, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such user.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getpwnam_r should be its input passwd.
-        return None;
+        return Ok(None);
     }
 
-    let user = unsafe { passwd_to_user(result.read()) };
-    Some(user)
+    let user = unsafe { convert(result.read()) };
+    Ok(Some(user))
 }
 
 /// Searches for a `Group` with the given ID in the system’s group database.
@@ -439,39 +568,59 @@ pub fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(username: &S) -> Option<User>
 /// }
 /// ```
 pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
+    try_get_group_by_gid(gid).unwrap_or_default()
+}
+
+/// Searches for a `Group` with the given ID, like `get_group_by_gid`, but
+/// returns `Err` if the C library reports an error.
+pub(crate) fn try_get_group_by_gid(gid: gid_t) -> io::Result<Option<Group>> {
+    lookup_group_by_gid(gid, struct_to_group)
+}
+
+/// Looks up the group with the given ID, converting the C library’s result
+/// with the given function.
+fn lookup_group_by_gid<T>(gid: gid_t, convert: unsafe fn(c_group) -> T) -> io::Result<Option<T>> {
     let mut passwd = unsafe { mem::zeroed::<c_group>() };
     let mut buf = vec![0; 2048];
     let mut result = ptr::null_mut::<c_group>();
 
     #[cfg(feature = "logging")]
-    trace!("Running getgruid_r for group #{}", gid);
+    trace!("Running getgrgid_r for group #{}", gid);
+    #[cfg(feature = "logging")]
+    let started = Instant::now();
 
-    loop {
+    let r = loop {
         let r = unsafe {
             libc::getgrgid_r(gid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
         };
 
         if r != libc::ERANGE {
-            break;
+            break r;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
+        let newsize = buf.len().checked_mul(2).ok_or_else(|| io::Error::from_raw_os_error(libc::ERANGE))?;
         buf.resize(newsize, 0);
+    };
+
+    #[cfg(feature = "logging")]
+    log_elapsed("getgrgid_r", started);
+
+    if let Some(e) = lookup_error(r) {
+        return Err(e);
     }
 
     if result.is_null() {
-        // There is no such group, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such group.
+        return Ok(None);
     }
 
     if result != &mut passwd {
         // The result of getgrgid_r should be its input struct.
-        return None;
+        return Ok(None);
     }
 
-    let group = unsafe { struct_to_group(result.read()) };
-    Some(group)
+    let group = unsafe { convert(result.read()) };
+    Ok(Some(group))
 }
 
 /// Searches for a `Group` with the given group name in the system’s group database.
@@ -492,12 +641,24 @@ pub fn get_group_by_gid(gid: gid_t) -> Option<Group> {
 /// }
 /// ```
 pub fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> Option<Group> {
-    let groupname = match CString::new(groupname.as_ref().as_bytes()) {
+    try_get_group_by_name(groupname).unwrap_or_default()
+}
+
+/// Searches for a `Group` with the given group name, like
+/// `get_group_by_name`, but returns `Err` if the C library reports an error.
+pub(crate) fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> io::Result<Option<Group>> {
+    lookup_group_by_name(groupname.as_ref(), struct_to_group)
+}
+
+/// Looks up the group with the given name, converting the C library’s result
+/// with the given function.
+fn lookup_group_by_name<T>(groupname: &OsStr, convert: unsafe fn(c_group) -> T) -> io::Result<Option<T>> {
+    let groupname = match CString::new(groupname.as_bytes()) {
         Ok(u)  => u,
         Err(_) => {
             // The groupname that was passed in contained a null character,
             // which will match no usernames.
-            return None;
+            return Ok(None);
         }
     };
 
@@ -507,33 +668,65 @@ pub fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(groupname: &S) -> Option<Grou
 
     #[cfg(feature = "logging")]
     trace!("Running getgrnam_r for group {:?}", groupname.as_ref());
+    #[cfg(feature = "logging")]
+    let started = Instant::now();
 
-    loop {
+    let r = loop {
         let r = unsafe {
             libc::getgrnam_r(groupname.as_ptr(), &mut group, buf.as_mut_ptr(), buf.len(), &mut result)
         };
 
         if r != libc::ERANGE {
-            break;
+            break r;
         }
 
-        let newsize = buf.len().checked_mul(2)?;
+        let newsize = buf.len().checked_mul(2).ok_or_else(|| io::Error::from_raw_os_error(libc::ERANGE))?;
         buf.resize(newsize, 0);
+    };
+
+    #[cfg(feature = "logging")]
+    log_elapsed("getgrnam_r", started);
+
+    if let Some(e) = lookup_error(r) {
+        return Err(e);
     }
 
     if result.is_null() {
-        // There is no such group, or an error has occurred.
-        // errno gets set if there’s an error.
-        return None;
+        // There is no such group.
+        return Ok(None);
     }
 
     if result != &mut group {
         // The result of getgrnam_r should be its input struct.
-        return None;
+        return Ok(None);
     }
 
-    let group = unsafe { struct_to_group(result.read()) };
-    Some(group)
+    let group = unsafe { convert(result.read()) };
+    Ok(Some(group))
+}
+
+/// Searches for a user with the given ID, like `get_user_by_uid`, but only
+/// reads its IDs and name.
+pub(crate) fn get_bare_user_by_uid(uid: uid_t) -> Option<User> {
+    try_get_bare_user_by_uid(uid).unwrap_or_default()
+}
+
+/// Searches for a user with the given ID, like `get_bare_user_by_uid`, but
+/// returns `Err` if the C library reports an error.
+pub(crate) fn try_get_bare_user_by_uid(uid: uid_t) -> io::Result<Option<User>> {
+    lookup_user_by_uid(uid, passwd_to_bare_user)
+}
+
+/// Searches for a group with the given ID, like `get_group_by_gid`, but only
+/// reads its ID and name.
+pub(crate) fn get_bare_group_by_gid(gid: gid_t) -> Option<Group> {
+    try_get_bare_group_by_gid(gid).unwrap_or_default()
+}
+
+/// Searches for a group with the given ID, like `get_bare_group_by_gid`, but
+/// returns `Err` if the C library reports an error.
+pub(crate) fn try_get_bare_group_by_gid(gid: gid_t) -> io::Result<Option<Group>> {
+    lookup_group_by_gid(gid, struct_to_bare_group)
 }
 
 /// Returns the user ID for the user running the process.
@@ -773,43 +966,119 @@ pub fn group_access_list() -> io::Result<Vec<Group>> {
 /// }
 /// ```
 pub fn get_user_groups<S: AsRef<OsStr> + ?Sized>(username: &S, gid: gid_t) -> Option<Vec<Group>> {
+    let gids = get_user_group_ids(username, gid)?;
+
+    gids.into_iter()
+        .filter_map(get_group_by_gid)
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Returns the IDs of the groups for a provided user name and primary group
+/// id, including any that have no entry in the group database.
+#[allow(trivial_numeric_casts)]
+pub(crate) fn get_user_group_ids<S: AsRef<OsStr> + ?Sized>(username: &S, gid: gid_t) -> Option<Vec<gid_t>> {
     // MacOS uses i32 instead of gid_t in getgrouplist for unknown reasons
     #[cfg(all(unix, target_os="macos"))]
     let mut buff: Vec<i32> = vec![0; 1024];
     #[cfg(all(unix, not(target_os="macos")))]
     let mut buff: Vec<gid_t> = vec![0; 1024];
 
-    let name = CString::new(username.as_ref().as_bytes()).unwrap();
-    let mut count = buff.len() as c_int;
+    let name = match CString::new(username.as_ref().as_bytes()) {
+        Ok(n)  => n,
+        Err(_) => {
+            // The username that was passed in contained a null character,
+            // which will match no usernames.
+            return None;
+        }
+    };
 
     #[cfg(feature = "logging")]
     trace!("Running getgrouplist for user {:?} and group #{}", username.as_ref(), gid);
 
-    // MacOS uses i32 instead of gid_t in getgrouplist for unknown reasons
-    #[cfg(all(unix, target_os="macos"))]
-    let res = unsafe {
-        libc::getgrouplist(name.as_ptr(), gid as i32, buff.as_mut_ptr(), &mut count)
-    };
+    loop {
+        let mut count = buff.len() as c_int;
 
-    #[cfg(all(unix, not(target_os="macos")))]
-    let res = unsafe {
-        libc::getgrouplist(name.as_ptr(), gid, buff.as_mut_ptr(), &mut count)
-    };
+        // MacOS uses i32 instead of gid_t in getgrouplist for unknown reasons
+        #[cfg(all(unix, target_os="macos"))]
+        let res = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid as i32, buff.as_mut_ptr(), &mut count)
+        };
 
-    if res < 0 {
-        None
-    }
-    else {
-        buff.dedup();
-        buff.into_iter()
-            .filter_map(|i| get_group_by_gid(i as gid_t))
-            .collect::<Vec<_>>()
-            .into()
+        #[cfg(all(unix, not(target_os="macos")))]
+        let res = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid, buff.as_mut_ptr(), &mut count)
+        };
+
+        if res >= 0 {
+            // Only the first `count` entries were filled in; the rest of the
+            // buffer is still zeroes, which would otherwise read as root.
+            buff.truncate(count as usize);
+            break;
+        }
+
+        // The buffer was too small: some platforms tell us how big it needs
+        // to be, and the others get it doubled, up to Linux’s NGROUPS_MAX.
+        let newsize = if count as usize > buff.len() {
+            count as usize
+        }
+        else {
+            buff.len().checked_mul(2)?
+        };
+
+        if newsize > 65536 {
+            return None;
+        }
+
+        buff.resize(newsize, 0);
     }
+
+    let mut gids = buff.into_iter().map(|i| i as gid_t).collect::<Vec<_>>();
+    gids.dedup();
+    Some(gids)
 }
 
 
 
+/// Locks the mutex that makes sure this crate’s own safe functions only run
+/// one iteration over the users or groups database at a time, waiting for
+/// any other one to finish first.
+///
+/// Every caller in the process shares libc’s position in each database, so
+/// two iterations running at once would each skip the entries the other one
+/// read. Iterating over users and groups at the same time is safe, but
+/// rare enough that one lock covers both.
+///
+/// The lock is only ever held while a whole database gets read into a
+/// `Vec`, never while other code runs, so it can’t be taken twice by the
+/// same thread. It’s created the first time it’s needed, as `Mutex::new`
+/// can’t be called in a `static`.
+pub(crate) fn enumeration_lock() -> MutexGuard<'static, ()> {
+    static INIT: Once = Once::new();
+    static LOCK: AtomicPtr<Mutex<()>> = AtomicPtr::new(ptr::null_mut());
+
+    INIT.call_once(|| LOCK.store(Box::into_raw(Box::new(Mutex::new(()))), Ordering::Release));
+
+    // The mutex is never freed, and the `Once` makes sure it exists by now.
+    let mutex = unsafe { &*LOCK.load(Ordering::Acquire) };
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Reads every user present on the system, after waiting for any other
+/// iteration run by this crate to finish.
+pub(crate) fn read_all_users() -> Vec<User> {
+    let _lock = enumeration_lock();
+    unsafe { all_users() }.collect()
+}
+
+/// Reads every group present on the system, after waiting for any other
+/// iteration run by this crate to finish.
+pub(crate) fn read_all_groups() -> Vec<Group> {
+    let _lock = enumeration_lock();
+    unsafe { all_groups() }.collect()
+}
+
+
 /// An iterator over every user present on the system.
 struct AllUsers;
 
@@ -840,6 +1109,12 @@ struct AllUsers;
 /// block, then make sure to not make a new instance of it until
 /// iteration is over.
 ///
+/// This crate’s own safe functions that read the whole database, such as
+/// the ones that fill a [`UsersCache`](cache/struct.UsersCache.html) or
+/// find a group’s members, take turns with each other, but they have no way
+/// of knowing about iterators returned from this function. Avoid calling
+/// them while one is still being used.
+///
 /// # Examples
 ///
 /// ```
@@ -900,6 +1175,83 @@ impl Iterator for AllUsers {
 }
 
 
+/// An iterator over every group present on the system.
+struct AllGroups;
+
+/// Creates a new iterator over every group present on the system.
+///
+/// # libc functions used
+///
+/// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+/// - [`setgrent`](https://docs.rs/libc/*/libc/fn.setgrent.html)
+/// - [`endgrent`](https://docs.rs/libc/*/libc/fn.endgrent.html)
+///
+/// # Safety
+///
+/// This constructor is `unsafe` for the same reason as
+/// [`all_users`](fn.all_users.html): the C functions it uses modify global
+/// state, and nothing can stop other code from calling them at the same time.
+///
+/// # Examples
+///
+/// ```
+/// use users::all_groups;
+///
+/// let iter = unsafe { all_groups() };
+/// for group in iter {
+///     println!("Group #{} ({:?})", group.gid(), group.name());
+/// }
+/// ```
+pub unsafe fn all_groups() -> impl Iterator<Item=Group> {
+    #[cfg(feature = "logging")]
+    trace!("Running setgrent");
+
+    #[cfg(not(target_os = "android"))]
+    libc::setgrent();
+    AllGroups
+}
+
+impl Drop for AllGroups {
+    #[cfg(target_os = "android")]
+    fn drop(&mut self) {
+        // nothing to do here
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn drop(&mut self) {
+        #[cfg(feature = "logging")]
+        trace!("Running endgrent");
+
+        unsafe { libc::endgrent() };
+    }
+}
+
+impl Iterator for AllGroups {
+    type Item = Group;
+
+    #[cfg(target_os = "android")]
+    fn next(&mut self) -> Option<Group> {
+        None
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn next(&mut self) -> Option<Group> {
+        #[cfg(feature = "logging")]
+        trace!("Running getgrent");
+
+        let result = unsafe { libc::getgrent() };
+
+        if result.is_null() {
+            None
+        }
+        else {
+            let group = unsafe { struct_to_group(result.read()) };
+            Some(group)
+        }
+    }
+}
+
+
 
 /// OS-specific extensions to users and groups.
 ///
@@ -1010,6 +1362,17 @@ pub mod os {
                     Self { home_dir, shell, password }
                 }
             }
+
+            #[cfg(feature = "cache")]
+            pub(crate) fn heap_size(&self) -> usize {
+                self.home_dir.as_os_str().len() + self.shell.as_os_str().len() + self.password.len()
+            }
+
+            /// Returns extras with every field empty, which, unlike the
+            /// default values, don’t need allocating.
+            pub(crate) fn empty() -> Self {
+                Self { home_dir: PathBuf::new(), shell: PathBuf::new(), password: OsString::new() }
+            }
         }
 
         #[cfg(any(target_os = "linux", target_os = "android", target_os = "solaris"))]
@@ -1059,11 +1422,16 @@ pub mod os {
             pub(crate) unsafe fn from_struct(group: c_group) -> Self {
                 Self { members: members(group.gr_mem) }
             }
+
+            #[cfg(feature = "cache")]
+            pub(crate) fn heap_size(&self) -> usize {
+                self.members.iter().map(|m| m.len() + std::mem::size_of::<OsString>()).sum()
+            }
         }
 
         impl GroupExt for Group {
             fn members(&self) -> &[OsString] {
-                &*self.extras.members
+                &self.extras.members
             }
 
             fn add_member<S: AsRef<OsStr> + ?Sized>(mut self, member: &S) -> Self {
@@ -1109,6 +1477,15 @@ pub mod os {
                     extras: super::unix::UserExtras::from_passwd(passwd),
                 }
             }
+
+            #[cfg(feature = "cache")]
+            pub(crate) fn heap_size(&self) -> usize {
+                self.extras.heap_size()
+            }
+
+            pub(crate) fn empty() -> Self {
+                Self { extras: super::unix::UserExtras::empty(), change: 0, expire: 0 }
+            }
         }
 
         impl super::unix::UserExt for User {
@@ -1186,9 +1563,23 @@ pub mod os {
 
 
 #[cfg(test)]
+#[allow(clippy::borrow_deref_ref, clippy::len_zero)]
 mod test {
     use super::*;
 
+    #[test]
+    fn users_and_groups_at_once() {
+        // Neither iterator takes the lock, so holding both on one thread
+        // doesn’t wait forever. The lock is taken here only so that other
+        // tests don’t read the databases at the same time.
+        let _lock = enumeration_lock();
+        let mut users = unsafe { all_users() };
+        let mut groups = unsafe { all_groups() };
+
+        assert!(users.next().is_some());
+        assert!(groups.next().is_some());
+    }
+
     #[test]
     fn uid() {
         get_current_uid();
@@ -36,9 +36,9 @@
 //! and group lookups. Rust provides mutability in two ways:
 //!
 //! 1. Have its methods take `&mut self`, instead of `&self`, allowing the
-//!   internal maps to be mutated (“inherited mutability”)
-//! 2. Wrap the internal maps in a `RefCell`, allowing them to be modified
-//!   (“interior mutability”).
+//!    internal maps to be mutated (“inherited mutability”)
+//! 2. Wrap the internal maps in a `RefCell` or a lock, allowing them to be
+//!    modified (“interior mutability”).
 //!
 //! Unfortunately, Rust is also very protective of references to a mutable
 //! value. In this case, switching to `&mut self` would only allow for one user
@@ -62,7 +62,7 @@
 //!
 //! The compiler wasn’t on our side with Option 1, so let’s try Option 2:
 //! changing the methods back to `&self` instead of `&mut self`, and using
-//! `RefCell`s internally. However, Rust is smarter than this, and knows that
+//! `RefCell`s (or `RwLock`s) internally. However, Rust is smarter than this, and knows that
 //! we’re just trying the same trick as earlier. A simplified implementation of
 //! a user cache lookup would look something like this:
 //!
@@ -83,29 +83,164 @@
 //! the values themselves don’t count as being stored *in* the cache anymore. So
 //! it can be queried multiple times or go out of scope and the values it
 //! produces are not affected.
+//!
+//! The internal maps are each behind an `RwLock`, so a `UsersCache` is both
+//! `Send` and `Sync`, and can be shared between threads by putting it in an
+//! `Arc`. Looking up an entry that’s already in the cache only needs a read
+//! lock, so threads don’t wait for each other, and the locks are never held
+//! while the OS is being queried, so one slow lookup doesn’t hold up the
+//! others.
 
 use libc::{uid_t, gid_t};
-use std::cell::{Cell, RefCell};
-use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::sync::Arc;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use base::{User, Group, all_users};
+use base::{User, Group, read_all_users, read_all_groups};
+use base::{try_get_bare_user_by_uid, try_get_bare_group_by_gid};
+use base::{try_get_user_by_uid, try_get_user_by_name, try_get_group_by_gid, try_get_group_by_name};
+use base::get_user_group_ids;
+use base::os::unix::{UserExt, GroupExt};
+use switch::credentials_generation;
+use system::find_group_members;
 use traits::{Users, Groups};
 
 
 /// A producer of user and group instances that caches every result.
 ///
+/// By default, entries are kept forever. To have them looked up again
+/// after a while, or when the users and groups databases change, use the
+/// `with_*` methods when creating the cache. Lookups that fail with an
+/// error, which the `try_get_*` methods return, don’t get cached at all.
+///
+/// The current and effective user and group IDs are cached too. They expire
+/// along with the positive entries, get emptied along with everything else
+/// when a watched file changes, and are read again after any of the
+/// functions in the [`switch`](../switch/index.html) module have changed
+/// them. If they get changed some other way, such as by calling libc
+/// directly, call [`clear`](#method.clear) afterwards.
+///
+/// Finding a group’s members reads the whole users database, so it
+/// shouldn’t be done while iterating over [`all_users`](../fn.all_users.html).
+///
 /// For more information, see the [`users::cache` module documentation](index.html).
 pub struct UsersCache {
-    users:  BiMap<uid_t, User>,
-    groups: BiMap<gid_t, Group>,
+    users:  BiMap<User>,
+    groups: BiMap<Group>,
+
+    /// Users and groups with only their IDs and names, looked up in
+    /// names-only mode, kept apart so full lookups never return them.
+    bare_users:  BiMap<User>,
+    bare_groups: BiMap<Group>,
+
+    memberships: Memberships,
+
+    uid:  IdSlot<uid_t>,
+    gid:  IdSlot<gid_t>,
+    euid: IdSlot<uid_t>,
+    egid: IdSlot<gid_t>,
+
+    names_only: bool,
+    list_cached: bool,
+}
+
+/// The number of entries in a `UsersCache`, and roughly how much memory they
+/// take up.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub struct CacheSize {
+
+    /// The number of user IDs that have been looked up, including ones that
+    /// were not found.
+    pub users: usize,
+
+    /// The number of usernames that have been looked up, including ones that
+    /// were not found.
+    pub user_names: usize,
+
+    /// The number of group IDs that have been looked up, including ones that
+    /// were not found.
+    pub groups: usize,
+
+    /// The number of group names that have been looked up, including ones
+    /// that were not found.
+    pub group_names: usize,
+
+    /// The number of users whose groups have been looked up, including ones
+    /// that were not found.
+    pub memberships: usize,
+
+    /// An estimate of the number of bytes taken up by all the entries. Users
+    /// and groups that are still in use outside the cache are counted too, so
+    /// this is how much memory would be freed if *nothing* else held on to
+    /// them.
+    pub bytes: usize,
+}
+
+/// How well a `UsersCache` has been answering lookups, as returned by
+/// [`UsersCache::stats`](struct.UsersCache.html#method.stats).
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub struct CacheStats {
+
+    /// Lookups of users by their ID.
+    pub users_by_id: LookupStats,
+
+    /// Lookups of users by their name.
+    pub users_by_name: LookupStats,
+
+    /// Lookups of groups by their ID.
+    pub groups_by_id: LookupStats,
+
+    /// Lookups of groups by their name.
+    pub groups_by_name: LookupStats,
+
+    /// Lookups of the groups that a user is a member of.
+    pub memberships: LookupStats,
+}
 
-    uid:  Cell<Option<uid_t>>,
-    gid:  Cell<Option<gid_t>>,
-    euid: Cell<Option<uid_t>>,
-    egid: Cell<Option<gid_t>>,
+/// The counters for one kind of lookup in a `UsersCache`.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub struct LookupStats {
+
+    /// The number of lookups that were answered with a user or group from
+    /// the cache.
+    pub hits: usize,
+
+    /// The number of lookups that were answered from the cache with a user
+    /// or group that had already been found not to exist.
+    pub negative_hits: usize,
+
+    /// The number of lookups that the cache had no answer for, including
+    /// ones where the entry had expired.
+    pub misses: usize,
+
+    /// The number of times the C library was called to answer these
+    /// lookups.
+    pub libc_calls: usize,
+}
+
+/// One of the current process’s IDs, if it has been read.
+type IdSlot<T> = Mutex<Option<CachedId<T>>>;
+
+/// One of the current process’s IDs, along with when it was read, the value
+/// of the `switch` module’s credentials counter at the time, so it can be
+/// read again if the process has switched users since then, and how many
+/// times the map it goes with had been emptied.
+#[derive(Copy, Clone)]
+struct CachedId<T> {
+    id: T,
+    fetched: Instant,
+    generation: usize,
+    flushes: usize,
 }
 
 /// A kinda-bi-directional `HashMap` that associates keys to values, and
@@ -115,246 +250,2063 @@ pub struct UsersCache {
 /// only want to search based on usernames and group names. There wouldn’t be
 /// much point offering a “User to uid” map, as the uid is present in the
 /// `User` struct!
-struct BiMap<K, V> {
-    forward:  RefCell< HashMap<K, Option<Arc<V>>> >,
-    backward: RefCell< HashMap<Arc<OsStr>, Option<K>> >,
+///
+/// When both maps need locking, the forward one always gets locked first, so
+/// two threads can’t each end up waiting for the other’s lock.
+///
+/// If the maps have a maximum size, each entry records when it was last used,
+/// according to a clock that ticks once per use, so the least recently used
+/// ones can be thrown away. Every name in the backward map that resolves to
+/// an ID has an entry for that ID in the forward map, so evicting an ID
+/// evicts its names as well.
+struct BiMap<V: Entity> {
+    forward:  RwLock<Forward<V>>,
+    backward: RwLock<Backward<V>>,
+
+    expiry: Expiry,
+    watch: Option<FileWatch>,
+
+    max_entries: Option<usize>,
+    clock: AtomicUsize,
+
+    /// The number of times the maps have been emptied.
+    flushes: AtomicUsize,
+
+    by_id:   Counters,
+    by_name: Counters,
 }
 
+/// The running totals behind a `LookupStats`.
+#[derive(Default)]
+struct Counters {
+    hits: AtomicUsize,
+    negative_hits: AtomicUsize,
+    misses: AtomicUsize,
+    libc_calls: AtomicUsize,
+}
 
-// Default has to be impl’d manually here, because there’s no
-// Default impl on User or Group, even though those types aren’t
-// needed to produce a default instance of any HashMaps...
-impl Default for UsersCache {
-    fn default() -> Self {
-        Self {
-            users: BiMap {
-                forward:  RefCell::new(HashMap::new()),
-                backward: RefCell::new(HashMap::new()),
-            },
+impl Counters {
+
+    /// Counts the result of looking something up in one of the maps.
+    fn record<T>(&self, result: &Option<Option<T>>) {
+        let counter = match *result {
+            Some(Some(_)) => &self.hits,
+            Some(None)    => &self.negative_hits,
+            None          => &self.misses,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
 
-            groups: BiMap {
-                forward:  RefCell::new(HashMap::new()),
-                backward: RefCell::new(HashMap::new()),
-            },
+    /// Counts a call to the C library, and makes it.
+    fn call<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        self.libc_calls.fetch_add(1, Ordering::Relaxed);
+        f()
+    }
+
+    /// Returns the totals of these counters and the given ones.
+    fn snapshot(&self, other: &Counters) -> LookupStats {
+        let total = |a: &AtomicUsize, b: &AtomicUsize| a.load(Ordering::Relaxed) + b.load(Ordering::Relaxed);
 
-            uid:  Cell::new(None),
-            gid:  Cell::new(None),
-            euid: Cell::new(None),
-            egid: Cell::new(None),
+        LookupStats {
+            hits:          total(&self.hits, &other.hits),
+            negative_hits: total(&self.negative_hits, &other.negative_hits),
+            misses:        total(&self.misses, &other.misses),
+            libc_calls:    total(&self.libc_calls, &other.libc_calls),
         }
     }
 }
 
+type Forward<V>  = HashMap<<V as Entity>::Id, Cached<Option<Arc<V>>>>;
+type Backward<V> = HashMap<Arc<OsStr>, Cached<Option<<V as Entity>::Id>>>;
 
-impl UsersCache {
+/// A value in one of the maps, along with when it was looked up and last
+/// used.
+struct Cached<T> {
+    value: T,
+    fetched: Instant,
+    last_used: AtomicUsize,
+}
 
-    /// Creates a new empty cache.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use users::cache::UsersCache;
-    ///
-    /// let cache = UsersCache::new();
-    /// ```
-    pub fn new() -> Self {
-        Self::default()
+/// How long entries can stay in the cache before they have to be looked up
+/// again. Entries for users and groups that exist are *positive*, and
+/// entries recording that they don’t are *negative*.
+#[derive(Copy, Clone, Default)]
+struct Expiry {
+    positive: Option<Duration>,
+    negative: Option<Duration>,
+}
+
+impl Expiry {
+    fn is_fresh<T>(&self, entry: &Cached<Option<T>>) -> bool {
+        let ttl = if entry.value.is_some() { self.positive } else { self.negative };
+        within(ttl, entry.fetched)
     }
 
-    /// Creates a new cache that contains all the users present on the system.
-    ///
-    /// # Safety
-    ///
-    /// This is `unsafe` because we cannot prevent data races if two caches
-    /// were attempted to be initialised on different threads at the same time.
-    /// For more information, see the [`all_users` documentation](../fn.all_users.html).
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use users::cache::UsersCache;
-    ///
-    /// let cache = unsafe { UsersCache::with_all_users() };
-    /// ```
-    pub unsafe fn with_all_users() -> Self {
-        let cache = Self::new();
+    /// Returns whether something that exists, and was looked up at the given
+    /// time, is still fresh.
+    fn is_positive_fresh(&self, fetched: Instant) -> bool {
+        within(self.positive, fetched)
+    }
+}
+
+fn within(ttl: Option<Duration>, fetched: Instant) -> bool {
+    match ttl {
+        Some(ttl) => fetched.elapsed() < ttl,
+        None      => true,
+    }
+}
+
+/// A database file whose modification time is checked before every lookup,
+/// emptying the cache when it changes.
+pub(crate) struct FileWatch {
+    path: PathBuf,
+    mtime: Mutex<Option<SystemTime>>,
+}
+
+impl FileWatch {
+    pub(crate) fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let mtime = Mutex::new(mtime(&path));
+        Self { path, mtime }
+    }
+
+    /// Returns whether the file has changed since the last time this was
+    /// called.
+    pub(crate) fn has_changed(&self) -> bool {
+        let current = mtime(&self.path);
+        let mut last = lock(&self.mtime);
 
-        for user in all_users() {
-            let uid = user.uid();
-            let user_arc = Arc::new(user);
-            cache.users.forward.borrow_mut().insert(uid, Some(Arc::clone(&user_arc)));
-            cache.users.backward.borrow_mut().insert(Arc::clone(&user_arc.name_arc), Some(uid));
+        if *last == current {
+            false
         }
+        else {
+            *last = current;
+            true
+        }
+    }
+}
 
-        cache
+fn mtime<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Something that can be stored in a `BiMap`, which has to know how to get
+/// an ID and a name out of it, and how to save itself to a file.
+trait Entity: Sized {
+    type Id: Copy + Eq + Hash + Into<u32> + From<u32>;
+
+    fn id(&self) -> Self::Id;
+    fn name_arc(&self) -> &Arc<OsStr>;
+    fn heap_size(&self) -> usize;
+
+    fn save<W: Write>(&self, w: &mut W) -> io::Result<()>;
+    fn load<R: Read>(r: &mut R) -> io::Result<Self>;
+}
+
+impl Entity for User {
+    type Id = uid_t;
+
+    fn id(&self) -> uid_t { self.uid() }
+    fn name_arc(&self) -> &Arc<OsStr> { &self.name_arc }
+    fn heap_size(&self) -> usize { User::heap_size(self) }
+
+    // The password and any OS-specific fields are not saved: they are rarely
+    // needed, and the password has no business being in a cache file.
+    fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, self.uid())?;
+        write_bytes(w, self.name().as_bytes())?;
+        write_u32(w, self.primary_group_id())?;
+        write_bytes(w, self.home_dir().as_os_str().as_bytes())?;
+        write_bytes(w, self.shell().as_os_str().as_bytes())
+    }
+
+    fn load<R: Read>(r: &mut R) -> io::Result<Self> {
+        let uid = read_u32(r)?;
+        let name = read_bytes(r)?;
+        let primary_group = read_u32(r)?;
+        let home_dir = read_bytes(r)?;
+        let shell = read_bytes(r)?;
+
+        Ok(User::new(uid, &name, primary_group)
+            .with_home_dir(&home_dir)
+            .with_shell(&shell))
     }
 }
 
+impl Entity for Group {
+    type Id = gid_t;
 
-// TODO: stop using ‘Arc::from’ with entry API
-// The ‘get_*_by_name’ functions below create a new Arc before even testing if
-// the user exists in the cache, essentially creating an unnecessary Arc.
-// https://internals.rust-lang.org/t/pre-rfc-abandonning-morals-in-the-name-of-performance-the-raw-entry-api/7043/51
-// https://github.com/rust-lang/rfcs/pull/1769
+    fn id(&self) -> gid_t { self.gid() }
+    fn name_arc(&self) -> &Arc<OsStr> { &self.name_arc }
+    fn heap_size(&self) -> usize { Group::heap_size(self) }
 
+    fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_u32(w, self.gid())?;
+        write_bytes(w, self.name().as_bytes())?;
+        write_u32(w, self.members().len() as u32)?;
 
-impl Users for UsersCache {
-    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
-        let mut users_forward = self.users.forward.borrow_mut();
+        for member in self.members() {
+            write_bytes(w, member.as_bytes())?;
+        }
 
-        let entry = match users_forward.entry(uid) {
-            Vacant(e) => e,
-            Occupied(e) => return e.get().as_ref().map(Arc::clone),
-        };
+        Ok(())
+    }
 
-        if let Some(user) = super::get_user_by_uid(uid) {
-            let newsername = Arc::clone(&user.name_arc);
-            let mut users_backward = self.users.backward.borrow_mut();
-            users_backward.insert(newsername, Some(uid));
+    fn load<R: Read>(r: &mut R) -> io::Result<Self> {
+        let gid = read_u32(r)?;
+        let name = read_bytes(r)?;
+        let mut group = Group::new(gid, &name);
 
-            let user_arc = Arc::new(user);
-            entry.insert(Some(Arc::clone(&user_arc)));
-            Some(user_arc)
+        for _ in 0 .. read_u32(r)? {
+            group = group.add_member(&read_bytes(r)?);
         }
-        else {
-            entry.insert(None);
-            None
+
+        Ok(group)
+    }
+}
+
+/// The entries of a `BiMap` that have been read from a file, along with
+/// how long before the file was saved each one was looked up.
+struct SavedEntries<V: Entity> {
+    forward: Vec<(V::Id, Duration, Option<V>)>,
+    backward: Vec<(OsString, Duration, Option<V::Id>)>,
+}
+
+impl<V: Entity> BiMap<V> {
+    fn new() -> Self {
+        Self {
+            forward:  RwLock::new(HashMap::new()),
+            backward: RwLock::new(HashMap::new()),
+            expiry:   Expiry::default(),
+            watch:    None,
+
+            max_entries: None,
+            clock:       AtomicUsize::new(0),
+            flushes:     AtomicUsize::new(0),
+
+            by_id:   Counters::default(),
+            by_name: Counters::default(),
         }
     }
 
-    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
-        let mut users_backward = self.users.backward.borrow_mut();
-
-        let entry = match users_backward.entry(Arc::from(username.as_ref())) {
-            Vacant(e) => e,
-            Occupied(e) => {
-                return (*e.get()).and_then(|uid| {
-                    let users_forward = self.users.forward.borrow_mut();
-                    users_forward[&uid].as_ref().map(Arc::clone)
-                })
+    /// Wraps a value that has just been looked up, ready to go in one of
+    /// the maps.
+    fn cached<T>(&self, value: T) -> Cached<T> {
+        Cached { value, fetched: Instant::now(), last_used: AtomicUsize::new(self.tick()) }
+    }
+
+    /// Marks an entry as having just been used.
+    fn touch<T>(&self, entry: &Cached<T>) {
+        if self.max_entries.is_some() {
+            entry.last_used.store(self.tick(), Ordering::Relaxed);
+        }
+    }
+
+    fn tick(&self) -> usize {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Empties the maps if the file being watched has changed.
+    fn check_watch(&self) {
+        if let Some(ref watch) = self.watch {
+            if watch.has_changed() {
+                self.clear();
+            }
+        }
+    }
+
+    /// Returns the cached result of looking up the given ID, or `None` if
+    /// it hasn’t been looked up yet or has expired.
+    fn get_by_id(&self, id: V::Id) -> Option<Option<Arc<V>>> {
+        self.check_watch();
+
+        let result = match read(&self.forward).get(&id) {
+            Some(entry) if self.expiry.is_fresh(entry) => {
+                self.touch(entry);
+                Some(entry.value.clone())
             }
+            _ => None,
         };
 
-        if let Some(user) = super::get_user_by_name(username) {
-            let uid = user.uid();
-            let user_arc = Arc::new(user);
+        self.by_id.record(&result);
+        result
+    }
+
+    /// Returns the cached result of looking up the given ID, without
+    /// counting it as a lookup or marking it as used.
+    fn find_by_id(&self, id: V::Id) -> Option<Option<Arc<V>>> {
+        read(&self.forward).get(&id).map(|entry| entry.value.clone())
+    }
+
+    /// Returns the cached result of looking up the given name, or `None` if
+    /// it hasn’t been looked up yet or has expired.
+    fn get_by_name(&self, name: &OsStr) -> Option<Option<Arc<V>>> {
+        self.check_watch();
 
-            let mut users_forward = self.users.forward.borrow_mut();
-            users_forward.insert(uid, Some(Arc::clone(&user_arc)));
-            entry.insert(Some(uid));
+        let result = self.find_by_name(name);
+        self.by_name.record(&result);
+        result
+    }
+
+    fn find_by_name(&self, name: &OsStr) -> Option<Option<Arc<V>>> {
+        let forward = read(&self.forward);
+        let backward = read(&self.backward);
+
+        let entry = match backward.get(name) {
+            Some(entry) if self.expiry.is_fresh(entry) => entry,
+            _                                          => return None,
+        };
 
-            Some(user_arc)
+        self.touch(entry);
+        match entry.value {
+            Some(id) => {
+                let found = forward.get(&id).filter(|e| self.expiry.is_fresh(e))?;
+                self.touch(found);
+                Some(found.value.clone())
+            }
+            None => Some(None),
         }
-        else {
-            entry.insert(None);
-            None
+    }
+
+    /// Returns the cached result of looking up the given ID, looking it up
+    /// with the given function and caching that if there isn’t one. Errors
+    /// don’t get cached.
+    fn get_or_insert_by_id<F>(&self, id: V::Id, f: F) -> io::Result<Option<Arc<V>>>
+    where F: FnOnce() -> io::Result<Option<V>>,
+    {
+        if let Some(cached) = self.get_by_id(id) {
+            return Ok(cached);
         }
+
+        let value = self.by_id.call(f)?;
+        Ok(self.insert_by_id(id, value))
     }
 
-    fn get_current_uid(&self) -> uid_t {
-        self.uid.get().unwrap_or_else(|| {
-            let uid = super::get_current_uid();
-            self.uid.set(Some(uid));
-            uid
-        })
+    /// Returns the cached result of looking up the given name, looking it up
+    /// with the given function and caching that if there isn’t one. Errors
+    /// don’t get cached.
+    fn get_or_insert_by_name<F>(&self, name: &OsStr, f: F) -> io::Result<Option<Arc<V>>>
+    where F: FnOnce() -> io::Result<Option<V>>,
+    {
+        if let Some(cached) = self.get_by_name(name) {
+            return Ok(cached);
+        }
+
+        let value = self.by_name.call(f)?;
+        Ok(self.insert_by_name(name, value))
     }
 
-    fn get_current_username(&self) -> Option<Arc<OsStr>> {
-        let uid = self.get_current_uid();
-        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    /// Stores the result of looking up the given ID, returning the value
+    /// that is now in the cache. If another thread got there first, this is
+    /// its value, so every caller gets the same `Arc`.
+    fn insert_by_id(&self, id: V::Id, value: Option<V>) -> Option<Arc<V>> {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        if let Some(existing) = forward.get(&id) {
+            if self.expiry.is_fresh(existing) {
+                return existing.value.clone();
+            }
+            else if let Some(ref old) = existing.value {
+                // The entry might have been renamed since it was cached.
+                backward.remove(old.name_arc());
+            }
+        }
+
+        let value = value.map(Arc::new);
+        if let Some(ref v) = value {
+            backward.insert(Arc::clone(v.name_arc()), self.cached(Some(id)));
+        }
+
+        forward.insert(id, self.cached(value.clone()));
+        self.evict(&mut forward, &mut backward);
+        value
     }
 
-    fn get_effective_uid(&self) -> uid_t {
-        self.euid.get().unwrap_or_else(|| {
-            let uid = super::get_effective_uid();
-            self.euid.set(Some(uid));
-            uid
-        })
+    /// Stores the result of looking up the given name, returning the value
+    /// that is now in the cache.
+    fn insert_by_name(&self, name: &OsStr, value: Option<V>) -> Option<Arc<V>> {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        let value = value.map(|v| {
+            let id = v.id();
+            let existing = forward.get(&id)
+                                  .filter(|e| self.expiry.is_fresh(e))
+                                  .and_then(|e| e.value.clone());
+
+            existing.unwrap_or_else(|| {
+                let arc = Arc::new(v);
+                forward.insert(id, self.cached(Some(Arc::clone(&arc))));
+                arc
+            })
+        });
+
+        backward.insert(Arc::from(name), self.cached(value.as_ref().map(|v| v.id())));
+        self.evict(&mut forward, &mut backward);
+        value
     }
 
-    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
-        let uid = self.get_effective_uid();
-        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    /// Stores a value that was found without looking it up by either its ID
+    /// or its name, such as when listing every entry, returning it.
+    fn insert(&self, value: V) -> Arc<V> {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        let id = value.id();
+        let value = Arc::new(value);
+        backward.insert(Arc::clone(value.name_arc()), self.cached(Some(id)));
+        forward.insert(id, self.cached(Some(Arc::clone(&value))));
+        self.evict(&mut forward, &mut backward);
+        value
     }
-}
 
+    /// Returns every value in the cache that hasn’t expired, in order of
+    /// their IDs.
+    fn values(&self) -> Vec<Arc<V>> {
+        self.check_watch();
 
-impl Groups for UsersCache {
-    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
-        let mut groups_forward = self.groups.forward.borrow_mut();
+        let forward = read(&self.forward);
+        let mut values = forward.values()
+            .filter(|e| self.expiry.is_fresh(e))
+            .filter_map(|e| e.value.clone())
+            .collect::<Vec<_>>();
+
+        values.sort_by_key(|v| v.id().into());
+        values
+    }
 
-        let entry = match groups_forward.entry(gid) {
-            Vacant(e) => e,
-            Occupied(e) => return e.get().as_ref().map(Arc::clone),
+    /// Throws away the least recently used entries if either map has grown
+    /// past its maximum size.
+    ///
+    /// Finding these entries means looking at all of them, so rather than
+    /// making room for one entry at a time, this shrinks the maps by an
+    /// eighth, so the next few insertions don’t have to do it again.
+    fn evict(&self, forward: &mut Forward<V>, backward: &mut Backward<V>) {
+        let max = match self.max_entries {
+            Some(max) => max,
+            None      => return,
         };
 
-        if let Some(group) = super::get_group_by_gid(gid) {
-            let new_group_name = Arc::clone(&group.name_arc);
-            let mut groups_backward = self.groups.backward.borrow_mut();
-            groups_backward.insert(new_group_name, Some(gid));
+        if forward.len() > max {
+            let count = forward.len() - (max - max / 8);
+            for id in least_recently_used(forward, count) {
+                forward.remove(&id);
+            }
 
-            let group_arc = Arc::new(group);
-            entry.insert(Some(Arc::clone(&group_arc)));
-            Some(group_arc)
+            backward.retain(|_, entry| match entry.value {
+                Some(id) => forward.contains_key(&id),
+                None     => true,
+            });
         }
-        else {
-            entry.insert(None);
-            None
+
+        if backward.len() > max {
+            let count = backward.len() - (max - max / 8);
+            for name in least_recently_used(backward, count) {
+                backward.remove(&name);
+            }
         }
     }
 
-    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
-        let mut groups_backward = self.groups.backward.borrow_mut();
-
-        let entry = match groups_backward.entry(Arc::from(group_name.as_ref())) {
-            Vacant(e) => e,
-            Occupied(e) => {
-                return (*e.get()).and_then(|gid| {
-                    let groups_forward = self.groups.forward.borrow_mut();
-                    groups_forward[&gid].as_ref().cloned()
-                });
+    /// Returns the number of entries in each map, and an estimate of how
+    /// many bytes they take up.
+    fn size(&self) -> (usize, usize, usize) {
+        let forward = read(&self.forward);
+        let backward = read(&self.backward);
+
+        let forward_bytes = forward.values()
+            .map(|e| mem::size_of::<(V::Id, Cached<Option<Arc<V>>>)>()
+                     + e.value.as_ref().map_or(0, |v| mem::size_of::<V>() + v.heap_size()))
+            .sum::<usize>();
+
+        let backward_bytes = backward.keys()
+            .map(|name| mem::size_of::<(Arc<OsStr>, Cached<Option<V::Id>>)>() + name.len())
+            .sum::<usize>();
+
+        (forward.len(), backward.len(), forward_bytes + backward_bytes)
+    }
+
+    /// Removes the entry for the given ID, along with any names that
+    /// resolved to it.
+    fn remove_id(&self, id: V::Id) {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        forward.remove(&id);
+        backward.retain(|_, entry| entry.value != Some(id));
+    }
+
+    /// Removes the entry for the given name, whether or not it was found,
+    /// along with the entry for the ID it resolved to.
+    fn remove_name(&self, name: &OsStr) {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        if let Some(Cached { value: Some(id), .. }) = backward.remove(name) {
+            forward.remove(&id);
+            backward.retain(|_, entry| entry.value != Some(id));
+        }
+    }
+
+    /// Removes every entry.
+    fn clear(&self) {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        forward.clear();
+        backward.clear();
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Writes every entry to a cache file, along with how long ago it was
+    /// looked up.
+    fn save<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let forward = read(&self.forward);
+        let backward = read(&self.backward);
+        let now = Instant::now();
+
+        write_u32(w, forward.len() as u32)?;
+        for (&id, entry) in forward.iter() {
+            write_u32(w, id.into())?;
+            write_duration(w, now.duration_since(entry.fetched))?;
+
+            match entry.value {
+                Some(ref v) => { w.write_all(&[1])?; v.save(w)?; }
+                None        => { w.write_all(&[0])?; }
             }
-        };
+        }
 
-        if let Some(group) = super::get_group_by_name(group_name) {
-            let group_arc = Arc::new(group.clone());
-            let gid = group.gid();
+        write_u32(w, backward.len() as u32)?;
+        for (name, entry) in backward.iter() {
+            write_bytes(w, name.as_bytes())?;
+            write_duration(w, now.duration_since(entry.fetched))?;
+
+            match entry.value {
+                Some(id) => { w.write_all(&[1])?; write_u32(w, id.into())?; }
+                None     => { w.write_all(&[0])?; }
+            }
+        }
 
-            let mut groups_forward = self.groups.forward.borrow_mut();
-            groups_forward.insert(gid, Some(Arc::clone(&group_arc)));
-            entry.insert(Some(gid));
+        Ok(())
+    }
 
-            Some(group_arc)
+    /// Reads the entries written by `save`, without adding them to the maps
+    /// yet, so nothing gets added if the file turns out to be corrupt.
+    fn load<R: Read>(r: &mut R) -> io::Result<SavedEntries<V>> {
+        let mut forward = Vec::new();
+        for _ in 0 .. read_u32(r)? {
+            let id = V::Id::from(read_u32(r)?);
+            let age = read_duration(r)?;
+            let value = if read_flag(r)? { Some(V::load(r)?) } else { None };
+            forward.push((id, age, value));
         }
-        else {
-            entry.insert(None);
-            None
+
+        let mut backward = Vec::new();
+        for _ in 0 .. read_u32(r)? {
+            let name = read_bytes(r)?;
+            let age = read_duration(r)?;
+            let id = if read_flag(r)? { Some(V::Id::from(read_u32(r)?)) } else { None };
+            backward.push((name, age, id));
         }
+
+        Ok(SavedEntries { forward, backward })
     }
 
-    fn get_current_gid(&self) -> gid_t {
-        self.gid.get().unwrap_or_else(|| {
-            let gid = super::get_current_gid();
-            self.gid.set(Some(gid));
-            gid
-        })
+    /// Adds entries that have been read from a file saved the given amount
+    /// of time ago. Entries that are already in the maps are kept, as they
+    /// are at least as new.
+    fn restore(&self, saved: SavedEntries<V>, since_saved: Duration) {
+        let mut forward = write(&self.forward);
+        let mut backward = write(&self.backward);
+
+        let now = Instant::now();
+        let fetched = |age: Duration| since_saved.checked_add(age).and_then(|d| now.checked_sub(d));
+
+        for (id, age, value) in saved.forward {
+            if let Some(fetched) = fetched(age) {
+                let last_used = AtomicUsize::new(self.tick());
+                forward.entry(id).or_insert_with(|| Cached { value: value.map(Arc::new), fetched, last_used });
+            }
+        }
+
+        for (name, age, id) in saved.backward {
+            let known = match id {
+                Some(id) => forward.contains_key(&id),
+                None     => true,
+            };
+
+            if let (true, Some(fetched)) = (known, fetched(age)) {
+                let last_used = AtomicUsize::new(self.tick());
+                backward.entry(Arc::from(name.as_os_str())).or_insert_with(|| Cached { value: id, fetched, last_used });
+            }
+        }
+
+        self.evict(&mut forward, &mut backward);
     }
+}
 
-    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
-        let gid = self.get_current_gid();
-        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+/// The IDs of the groups that users are members of, keyed by their names
+/// and the primary group IDs they were looked up with.
+///
+/// These go with the groups database, so they get emptied when it changes,
+/// and expire, get evicted, and are counted the same way as the entries in
+/// a `BiMap`.
+struct Memberships {
+    map: RwLock<MembershipMap>,
+
+    expiry: Expiry,
+    watch: Option<FileWatch>,
+
+    max_entries: Option<usize>,
+    clock: AtomicUsize,
+
+    counters: Counters,
+}
+
+type MembershipMap = HashMap<(Arc<OsStr>, gid_t), Cached<Option<Vec<gid_t>>>>;
+
+impl Memberships {
+    fn new() -> Self {
+        Self {
+            map:    RwLock::new(HashMap::new()),
+            expiry: Expiry::default(),
+            watch:  None,
+
+            max_entries: None,
+            clock:       AtomicUsize::new(0),
+
+            counters: Counters::default(),
+        }
     }
 
-    fn get_effective_gid(&self) -> gid_t {
-        self.egid.get().unwrap_or_else(|| {
-            let gid = super::get_effective_gid();
-            self.egid.set(Some(gid));
-            gid
-        })
+    fn tick(&self) -> usize {
+        self.clock.fetch_add(1, Ordering::Relaxed)
     }
 
-    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
-        let gid = self.get_effective_gid();
-        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    /// Returns the cached group IDs of the given user, looking them up with
+    /// the given function and caching them if they haven’t been looked up
+    /// yet or have expired.
+    fn get_or_insert<F>(&self, username: &OsStr, gid: gid_t, f: F) -> Option<Vec<gid_t>>
+    where F: FnOnce() -> Option<Vec<gid_t>>,
+    {
+        if let Some(ref watch) = self.watch {
+            if watch.has_changed() {
+                self.clear();
+            }
+        }
+
+        let key = (Arc::from(username), gid);
+        let result = match read(&self.map).get(&key) {
+            Some(entry) if self.expiry.is_fresh(entry) => {
+                if self.max_entries.is_some() {
+                    entry.last_used.store(self.tick(), Ordering::Relaxed);
+                }
+
+                Some(entry.value.clone())
+            }
+            _ => None,
+        };
+
+        self.counters.record(&result);
+        if let Some(cached) = result {
+            return cached;
+        }
+
+        let value = self.counters.call(f);
+        let mut map = write(&self.map);
+        map.insert(key, Cached { value: value.clone(), fetched: Instant::now(), last_used: AtomicUsize::new(self.tick()) });
+
+        if let Some(max) = self.max_entries {
+            if map.len() > max {
+                let count = map.len() - (max - max / 8);
+                for key in least_recently_used(&map, count) {
+                    map.remove(&key);
+                }
+            }
+        }
+
+        value
+    }
+
+    /// Returns the number of entries, and an estimate of how many bytes
+    /// they take up.
+    fn size(&self) -> (usize, usize) {
+        let map = read(&self.map);
+
+        let bytes = map.iter()
+            .map(|((name, _), e)| mem::size_of::<((Arc<OsStr>, gid_t), Cached<Option<Vec<gid_t>>>)>()
+                                  + name.len()
+                                  + e.value.as_ref().map_or(0, |gids| gids.capacity() * mem::size_of::<gid_t>()))
+            .sum::<usize>();
+
+        (map.len(), bytes)
+    }
+
+    /// Removes the entries for the user with the given name.
+    fn remove_name(&self, username: &OsStr) {
+        write(&self.map).retain(|(name, _), _| &**name != username);
+    }
+
+    /// Removes every entry.
+    fn clear(&self) {
+        write(&self.map).clear();
+    }
+}
+
+/// Returns the keys of the given number of least recently used entries in
+/// the given map.
+fn least_recently_used<K: Clone + Eq + Hash, T>(map: &HashMap<K, Cached<T>>, count: usize) -> Vec<K> {
+    let mut entries = map.iter()
+                         .map(|(k, e)| (e.last_used.load(Ordering::Relaxed), k))
+                         .collect::<Vec<_>>();
+
+    entries.sort_unstable_by_key(|&(last_used, _)| last_used);
+    entries.into_iter().take(count).map(|(_, k)| k.clone()).collect()
+}
+
+/// Locks the given lock for reading. A panicking thread can’t leave a map
+/// in an inconsistent state, so poisoning is ignored.
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks the given lock for writing, ignoring poisoning.
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Locks the given mutex, ignoring poisoning.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The bytes at the start of every cache file.
+const FILE_MAGIC: &[u8] = b"users-cache\0";
+
+/// The version of the cache file format. Files with any other version get
+/// ignored, rather than treated as corrupt.
+const FILE_VERSION: u32 = 2;
+
+/// The local users and groups databases, whose modification times get
+/// checked by file watching and when loading a cache file.
+pub(crate) const PASSWD_PATH: &str = "/etc/passwd";
+pub(crate) const GROUP_PATH: &str = "/etc/group";
+
+fn write_u32<W: Write>(w: &mut W, n: u32) -> io::Result<()> {
+    w.write_all(&n.to_be_bytes())
+}
+
+fn write_bytes<W: Write>(w: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_u32(w, bytes.len() as u32)?;
+    w.write_all(bytes)
+}
+
+fn write_duration<W: Write>(w: &mut W, duration: Duration) -> io::Result<()> {
+    w.write_all(&duration.as_secs().to_be_bytes())?;
+    write_u32(w, duration.subsec_nanos())
+}
+
+/// Writes a point in time that may not be known, such as a file’s
+/// modification time, as an optional duration since the Unix epoch.
+fn write_time<W: Write>(w: &mut W, time: Option<SystemTime>) -> io::Result<()> {
+    match time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(duration) => { w.write_all(&[1])?; write_duration(w, duration) }
+        None           => { w.write_all(&[0]) }
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_flag<R: Read>(r: &mut R) -> io::Result<bool> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+
+    match buf[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid flag in users cache file")),
+    }
+}
+
+/// Reads a length-prefixed string. The length isn’t trusted to allocate
+/// with, so a corrupt file can’t make this use up all the memory.
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<OsString> {
+    let len = read_u32(r)?;
+    let mut buf = Vec::new();
+    r.take(u64::from(len)).read_to_end(&mut buf)?;
+
+    if buf.len() == len as usize {
+        Ok(OsString::from_vec(buf))
+    }
+    else {
+        Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated users cache file"))
+    }
+}
+
+fn read_duration<R: Read>(r: &mut R) -> io::Result<Duration> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    let secs = u64::from_be_bytes(buf);
+    let nanos = read_u32(r)?;
+
+    if nanos < 1_000_000_000 {
+        Ok(Duration::new(secs, nanos))
+    }
+    else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid duration in users cache file"))
+    }
+}
+
+fn read_time<R: Read>(r: &mut R) -> io::Result<Option<SystemTime>> {
+    if read_flag(r)? {
+        let duration = read_duration(r)?;
+        Ok(UNIX_EPOCH.checked_add(duration))
+    }
+    else {
+        Ok(None)
+    }
+}
+
+/// Creates a new file next to the given path to write it under before
+/// renaming it, readable and writable only by its owner. The file must not
+/// already exist, so an existing file or symlink with the same name can’t
+/// be overwritten or followed; if one does exist, another name is tried.
+fn create_temp_file(path: &Path) -> io::Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    const ATTEMPTS: usize = 16;
+
+    let mut attempt = 0;
+    loop {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(format!(".{}.{}.{:08x}.tmp", process::id(), count, nanos));
+
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temp_path);
+
+        match result {
+            Ok(file) => return Ok((PathBuf::from(temp_path), file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt + 1 < ATTEMPTS => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Returns the ID in the given slot, filling it in with the result of the
+/// given function if it’s empty or out of date. It’s out of date if the
+/// process has switched users since it was read, if it’s older than the
+/// given map’s positive TTL, or if the map has been emptied since.
+fn get_or_set<T: Copy, V: Entity, F: FnOnce() -> T>(slot: &IdSlot<T>, map: &BiMap<V>, f: F) -> T {
+    map.check_watch();
+
+    let generation = credentials_generation();
+    let flushes = map.flushes.load(Ordering::Relaxed);
+    let mut slot = lock(slot);
+
+    match *slot {
+        Some(ref cached) if cached.generation == generation
+                         && cached.flushes == flushes
+                         && map.expiry.is_positive_fresh(cached.fetched) => cached.id,
+        _ => {
+            let id = f();
+            *slot = Some(CachedId { id, fetched: Instant::now(), generation, flushes });
+            id
+        }
+    }
+}
+
+
+// Default has to be impl’d manually here, because there’s no
+// Default impl on User or Group, even though those types aren’t
+// needed to produce a default instance of any HashMaps...
+impl Default for UsersCache {
+    fn default() -> Self {
+        Self {
+            users:  BiMap::new(),
+            groups: BiMap::new(),
+
+            bare_users:  BiMap::new(),
+            bare_groups: BiMap::new(),
+
+            memberships: Memberships::new(),
+
+            uid:  Mutex::new(None),
+            gid:  Mutex::new(None),
+            euid: Mutex::new(None),
+            egid: Mutex::new(None),
+
+            names_only: false,
+            list_cached: false,
+        }
+    }
+}
+
+
+impl UsersCache {
+
+    /// Creates a new empty cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new();
+    /// ```
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new cache that contains all the users present on the system.
+    ///
+    /// # Safety
+    ///
+    /// This is `unsafe` because we cannot prevent data races if two caches
+    /// were attempted to be initialised on different threads at the same time.
+    /// For more information, see the [`all_users` documentation](../fn.all_users.html).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = unsafe { UsersCache::with_all_users() };
+    /// ```
+    pub unsafe fn with_all_users() -> Self {
+        let cache = Self::new();
+        cache.preload_users();
+        cache
+    }
+
+    /// Creates a new cache that contains all the users and groups present on
+    /// the system.
+    ///
+    /// Unlike [`all_users`](../fn.all_users.html) and
+    /// [`all_groups`](../fn.all_groups.html), this is safe to call from any
+    /// thread, as this crate’s own safe functions only run one iteration over
+    /// the users or groups database at a time. It can’t know about iterators
+    /// returned from those two functions, though, so it shouldn’t be called
+    /// while one of them is still being used, as the two iterations would
+    /// each skip entries.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getpwent`](https://docs.rs/libc/*/libc/fn.getpwent.html)
+    /// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, Groups, UsersCache};
+    ///
+    /// let cache = UsersCache::with_all_users_and_groups();
+    /// let root = cache.get_group_by_gid(0);
+    /// ```
+    pub fn with_all_users_and_groups() -> Self {
+        let cache = Self::new();
+        cache.preload_users();
+        cache.preload_groups();
+        cache
+    }
+
+    /// Adds every user present on the system to the cache, replacing any
+    /// entries for them that were already there. In names-only mode, only
+    /// their IDs and names get added.
+    ///
+    /// Only users that exist get added, so looking up one that doesn’t will
+    /// still ask the C library. Like
+    /// [`with_all_users_and_groups`](#method.with_all_users_and_groups), this
+    /// waits for any other iteration over the database to finish.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getpwent`](https://docs.rs/libc/*/libc/fn.getpwent.html)
+    pub fn preload_users(&self) {
+        self.load_users();
+    }
+
+    /// Reads every user into the cache, returning them in the order the
+    /// database has them. In names-only mode, only the bare users get cached,
+    /// and the full ones are returned without being kept.
+    fn load_users(&self) -> Vec<Arc<User>> {
+        read_all_users().into_iter()
+             .map(|user| {
+                 if self.names_only {
+                     self.bare_users.insert(user.to_bare());
+                     Arc::new(user)
+                 }
+                 else {
+                     self.users.insert(user)
+                 }
+             })
+             .collect()
+    }
+
+    /// Adds every group present on the system to the cache, replacing any
+    /// entries for them that were already there. In names-only mode, only
+    /// their IDs and names get added.
+    ///
+    /// Only groups that exist get added, so looking up one that doesn’t will
+    /// still ask the C library. Like
+    /// [`with_all_users_and_groups`](#method.with_all_users_and_groups), this
+    /// waits for any other iteration over the database to finish.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+    pub fn preload_groups(&self) {
+        self.load_groups();
+    }
+
+    /// Reads every group into the cache, returning them in the order the
+    /// database has them, the same way as `load_users`.
+    fn load_groups(&self) -> Vec<Arc<Group>> {
+        read_all_groups().into_iter()
+              .map(|group| {
+                  if self.names_only {
+                      self.bare_groups.insert(group.to_bare());
+                      Arc::new(group)
+                  }
+                  else {
+                      self.groups.insert(group)
+                  }
+              })
+              .collect()
+    }
+
+    /// Returns the name of the user with the given ID from the names-only
+    /// entries, looking up its ID and name with the given function if they
+    /// aren’t there. Errors don’t get cached.
+    fn bare_user_name<F>(&self, uid: uid_t, lookup: F) -> Option<Arc<OsStr>>
+    where F: FnOnce() -> io::Result<Option<User>>,
+    {
+        let user = self.bare_users.get_or_insert_by_id(uid, lookup).unwrap_or_default();
+        user.map(|u| Arc::clone(&u.name_arc))
+    }
+
+    /// Returns the name of the group with the given ID from the names-only
+    /// entries, the same way as `bare_user_name`.
+    fn bare_group_name<F>(&self, gid: gid_t, lookup: F) -> Option<Arc<OsStr>>
+    where F: FnOnce() -> io::Result<Option<Group>>,
+    {
+        let group = self.bare_groups.get_or_insert_by_id(gid, lookup).unwrap_or_default();
+        group.map(|g| Arc::clone(&g.name_arc))
+    }
+
+    /// Makes entries for users and groups that exist expire after the given
+    /// amount of time, after which they get looked up again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new()
+    ///     .with_positive_ttl(Duration::from_secs(300))
+    ///     .with_negative_ttl(Duration::from_secs(10));
+    /// ```
+    pub fn with_positive_ttl(mut self, ttl: Duration) -> Self {
+        self.users.expiry.positive = Some(ttl);
+        self.groups.expiry.positive = Some(ttl);
+        self.bare_users.expiry.positive = Some(ttl);
+        self.bare_groups.expiry.positive = Some(ttl);
+        self.memberships.expiry.positive = Some(ttl);
+        self
+    }
+
+    /// Makes entries recording that a user or group does *not* exist expire
+    /// after the given amount of time, after which they get looked up again.
+    ///
+    /// This is usually set lower than the positive TTL, so that newly-created
+    /// users and groups are noticed quickly.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.users.expiry.negative = Some(ttl);
+        self.groups.expiry.negative = Some(ttl);
+        self.bare_users.expiry.negative = Some(ttl);
+        self.bare_groups.expiry.negative = Some(ttl);
+        self.memberships.expiry.negative = Some(ttl);
+        self
+    }
+
+    /// Makes the cache check the modification times of `/etc/passwd` and
+    /// `/etc/group` before every lookup, and empty the users or groups table
+    /// whenever the corresponding file changes.
+    ///
+    /// This only notices changes to the local files, not to users and groups
+    /// that come from somewhere else, such as LDAP, so it’s best combined with
+    /// a TTL on systems that use them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_file_watching();
+    /// ```
+    pub fn with_file_watching(mut self) -> Self {
+        self.users.watch = Some(FileWatch::new(PASSWD_PATH));
+        self.groups.watch = Some(FileWatch::new(GROUP_PATH));
+        self.bare_users.watch = Some(FileWatch::new(PASSWD_PATH));
+        self.bare_groups.watch = Some(FileWatch::new(GROUP_PATH));
+        self.memberships.watch = Some(FileWatch::new(GROUP_PATH));
+        self
+    }
+
+    /// Makes the cache hold on to only the IDs and names of users and groups
+    /// when only their names are asked for, for programs that look up a lot
+    /// of them just to display their names.
+    ///
+    /// This applies to `get_user_name_by_uid`, `get_group_name_by_gid`, and
+    /// the methods that return the current and effective user and group
+    /// names. For these, every field other than the IDs and names is left
+    /// behind in the C library’s buffers, which saves several allocations
+    /// per lookup, as well as the memory needed to keep them.
+    ///
+    /// These entries are kept apart from the rest of the cache, so the
+    /// methods that return whole users and groups still look them up in
+    /// full, with their home directories, shells, and members.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new().with_names_only();
+    /// let name = cache.get_user_name_by_uid(cache.get_current_uid());
+    /// ```
+    pub fn with_names_only(mut self) -> Self {
+        self.names_only = true;
+        self
+    }
+
+    /// Makes listing every user or group, with the `all_users` and
+    /// `all_groups` methods of the `Users` and `Groups` traits, list only the
+    /// ones already in the cache, in order of their IDs.
+    ///
+    /// Otherwise, listing reads the whole database, the same as
+    /// [`preload_users`](#method.preload_users) and
+    /// [`preload_groups`](#method.preload_groups) do, adding everything in
+    /// it to the cache.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new().with_cached_listing();
+    /// assert_eq!(cache.all_users().count(), 0);
+    ///
+    /// cache.get_user_by_uid(cache.get_current_uid());
+    /// assert_eq!(cache.all_users().count(), 1);
+    /// ```
+    pub fn with_cached_listing(mut self) -> Self {
+        self.list_cached = true;
+        self
+    }
+
+    /// Limits the number of entries the cache holds, throwing away the least
+    /// recently used ones when it fills up.
+    ///
+    /// The limit applies to the users and groups tables separately, and to
+    /// the entries for IDs and names within them. When one gets full, it gets
+    /// shrunk by an eighth of its size in one go, rather than making room
+    /// for one entry at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::cache::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_max_entries(10_000);
+    /// ```
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.users.max_entries = Some(max_entries);
+        self.groups.max_entries = Some(max_entries);
+        self.bare_users.max_entries = Some(max_entries);
+        self.bare_groups.max_entries = Some(max_entries);
+        self.memberships.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Returns the number of entries in the cache, and an estimate of how
+    /// much memory they take up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new();
+    /// cache.get_user_by_uid(cache.get_current_uid());
+    /// assert_eq!(cache.size().users, 1);
+    /// ```
+    pub fn size(&self) -> CacheSize {
+        let (users, user_names, user_bytes) = self.users.size();
+        let (groups, group_names, group_bytes) = self.groups.size();
+        let (bare_users, bare_user_names, bare_user_bytes) = self.bare_users.size();
+        let (bare_groups, bare_group_names, bare_group_bytes) = self.bare_groups.size();
+        let (memberships, membership_bytes) = self.memberships.size();
+
+        CacheSize {
+            users:       users + bare_users,
+            user_names:  user_names + bare_user_names,
+            groups:      groups + bare_groups,
+            group_names: group_names + bare_group_names,
+            memberships,
+            bytes:       user_bytes + group_bytes + bare_user_bytes + bare_group_bytes + membership_bytes,
+        }
+    }
+
+    /// Returns how many lookups the cache has answered by itself, and how
+    /// many it has had to pass on to the C library, since it was created.
+    ///
+    /// These counters keep going when entries are invalidated or the cache
+    /// gets cleared.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new();
+    /// let uid = cache.get_current_uid();
+    /// cache.get_user_by_uid(uid);
+    /// cache.get_user_by_uid(uid);
+    ///
+    /// let stats = cache.stats().users_by_id;
+    /// assert_eq!(stats.misses, 1);
+    /// assert_eq!(stats.libc_calls, 1);
+    /// ```
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            users_by_id:    self.users.by_id.snapshot(&self.bare_users.by_id),
+            users_by_name:  self.users.by_name.snapshot(&self.bare_users.by_name),
+            groups_by_id:   self.groups.by_id.snapshot(&self.bare_groups.by_id),
+            groups_by_name: self.groups.by_name.snapshot(&self.bare_groups.by_name),
+            memberships:    self.memberships.counters.snapshot(&Counters::default()),
+        }
+    }
+
+    /// Removes the user with the given ID from the cache, so the next time
+    /// it’s asked for, it gets looked up again. Any usernames that were found
+    /// to belong to that user are removed as well, along with the groups the
+    /// user was found to be a member of.
+    pub fn invalidate_user(&self, uid: uid_t) {
+        for map in &[&self.users, &self.bare_users] {
+            if let Some(Some(user)) = map.find_by_id(uid) {
+                self.memberships.remove_name(user.name());
+            }
+        }
+
+        self.users.remove_id(uid);
+        self.bare_users.remove_id(uid);
+    }
+
+    /// Removes the user with the given name from the cache, including if
+    /// the cache has recorded that no such user exists, along with the groups
+    /// the user was found to be a member of.
+    pub fn invalidate_user_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) {
+        self.memberships.remove_name(username.as_ref());
+        self.users.remove_name(username.as_ref());
+        self.bare_users.remove_name(username.as_ref());
+    }
+
+    /// Removes the group with the given ID from the cache, so the next time
+    /// it’s asked for, it gets looked up again. Any group names that were
+    /// found to belong to that group are removed as well.
+    ///
+    /// Any user could have joined or left the group, so every user’s group
+    /// memberships are removed too.
+    pub fn invalidate_group(&self, gid: gid_t) {
+        self.memberships.clear();
+        self.groups.remove_id(gid);
+        self.bare_groups.remove_id(gid);
+    }
+
+    /// Removes the group with the given name from the cache, including if
+    /// the cache has recorded that no such group exists, along with every
+    /// user’s group memberships.
+    pub fn invalidate_group_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) {
+        self.memberships.clear();
+        self.groups.remove_name(group_name.as_ref());
+        self.bare_groups.remove_name(group_name.as_ref());
+    }
+
+    /// Removes every entry from the cache, including users’ group memberships
+    /// and the current and effective user and group IDs.
+    pub fn clear(&self) {
+        self.users.clear();
+        self.groups.clear();
+        self.bare_users.clear();
+        self.bare_groups.clear();
+        self.memberships.clear();
+
+        *lock(&self.uid)  = None;
+        *lock(&self.gid)  = None;
+        *lock(&self.euid) = None;
+        *lock(&self.egid) = None;
+    }
+
+    /// Saves the users and groups in the cache to a file at the given path,
+    /// so that another process can start off with them by calling
+    /// [`load`](#method.load).
+    ///
+    /// Users and groups that were found not to exist are saved too, along
+    /// with how long ago every entry was looked up, so that any TTLs still
+    /// apply after they are loaded. Passwords and OS-specific fields are
+    /// *not* saved. The current and effective IDs are not saved either, as
+    /// they belong to this process, and nor are users’ group memberships.
+    ///
+    /// The file gets written under a new temporary name first, then renamed,
+    /// so that a process loading it never sees it half-written. It can only
+    /// be read by the user who saved it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while
+    /// writing the file.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use users::{Users, UsersCache};
+    ///
+    /// let cache = UsersCache::new();
+    /// cache.get_user_by_uid(cache.get_current_uid());
+    /// cache.save("/run/my-tool/users.cache").expect("Failed to save cache");
+    /// ```
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let (temp_path, file) = create_temp_file(path)?;
+
+        let result = self.write_to(io::BufWriter::new(file))
+            .and_then(|()| fs::rename(&temp_path, path));
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    /// Writes the users and groups in the cache to the given writer, in the
+    /// format used by [`save`](#method.save).
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while
+    /// writing.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(FILE_MAGIC)?;
+        write_u32(&mut writer, FILE_VERSION)?;
+        write_time(&mut writer, Some(SystemTime::now()))?;
+        write_time(&mut writer, mtime(PASSWD_PATH))?;
+        write_time(&mut writer, mtime(GROUP_PATH))?;
+        writer.write_all(&[self.names_only as u8])?;
+
+        self.users.save(&mut writer)?;
+        self.groups.save(&mut writer)?;
+        self.bare_users.save(&mut writer)?;
+        self.bare_groups.save(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Adds the users and groups saved to the file at the given path by
+    /// [`save`](#method.save) to this cache, returning whether it was used.
+    ///
+    /// The file is ignored, and `false` returned, if it doesn’t exist, if it
+    /// was written by an incompatible version of this crate, if it was saved
+    /// from a cache that was or wasn’t in
+    /// [names-only mode](#method.with_names_only) when this one isn’t or is,
+    /// or if either `/etc/passwd` or `/etc/group` has been modified since it
+    /// was saved.
+    /// As with [`with_file_watching`](#method.with_file_watching), changes to
+    /// users and groups that come from somewhere other than these files go
+    /// unnoticed, so loaded entries should be given a TTL on systems that use
+    /// them.
+    ///
+    /// Entries already in the cache are kept in preference to the ones in
+    /// the file, and any TTLs or size limit the cache was created with apply
+    /// to the loaded entries too.
+    ///
+    /// # Security considerations
+    ///
+    /// The contents of the file are trusted: anyone who can write to it can
+    /// make the cache return users and groups that don’t exist, or give
+    /// existing ones different IDs, home directories, or shells. Only load
+    /// files from a directory that nobody else can write to.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while
+    /// reading the file, or if the file is corrupt. Nothing gets added to the
+    /// cache in this case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use users::UsersCache;
+    ///
+    /// let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(300));
+    /// if !cache.load("/run/my-tool/users.cache").unwrap_or(false) {
+    ///     println!("Starting with an empty cache");
+    /// }
+    /// ```
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> io::Result<bool> {
+        match fs::File::open(path) {
+            Ok(file)                                         => self.read_from(io::BufReader::new(file)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e)                                           => Err(e),
+        }
+    }
+
+    /// Adds the users and groups read from the given reader, in the format
+    /// used by [`save`](#method.save), to this cache, returning whether they
+    /// were used. This works the same way as [`load`](#method.load).
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while
+    /// reading, or if the data is corrupt.
+    pub fn read_from<R: Read>(&self, reader: R) -> io::Result<bool> {
+        self.read_checked(reader, mtime(PASSWD_PATH), mtime(GROUP_PATH))
+    }
+
+    fn read_checked<R: Read>(&self, mut reader: R, passwd_mtime: Option<SystemTime>, group_mtime: Option<SystemTime>) -> io::Result<bool> {
+        let mut magic = [0; 12];
+        reader.read_exact(&mut magic)?;
+        if magic != FILE_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a users cache file"));
+        }
+
+        if read_u32(&mut reader)? != FILE_VERSION {
+            return Ok(false);
+        }
+
+        let saved_at = read_time(&mut reader)?;
+        if read_time(&mut reader)? != passwd_mtime || read_time(&mut reader)? != group_mtime {
+            return Ok(false);
+        }
+
+        if read_flag(&mut reader)? != self.names_only {
+            return Ok(false);
+        }
+
+        let users = BiMap::load(&mut reader)?;
+        let groups = BiMap::load(&mut reader)?;
+        let bare_users = BiMap::load(&mut reader)?;
+        let bare_groups = BiMap::load(&mut reader)?;
+
+        let since_saved = saved_at.and_then(|t| SystemTime::now().duration_since(t).ok())
+                                  .unwrap_or_else(|| Duration::from_secs(0));
+
+        self.users.restore(users, since_saved);
+        self.groups.restore(groups, since_saved);
+        self.bare_users.restore(bare_users, since_saved);
+        self.bare_groups.restore(bare_groups, since_saved);
+        Ok(true)
+    }
+}
+
+
+impl Users for UsersCache {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.try_get_user_by_uid(uid).unwrap_or_default()
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.try_get_user_by_name(username).unwrap_or_default()
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        self.users.get_or_insert_by_id(uid, || try_get_user_by_uid(uid))
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        let username = username.as_ref();
+        self.users.get_or_insert_by_name(username, || try_get_user_by_name(username))
+    }
+
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        if self.names_only { self.bare_user_name(uid, || try_get_bare_user_by_uid(uid)) }
+                      else { self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc)) }
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        get_or_set(&self.uid, &self.users, super::get_current_uid)
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_current_uid();
+        self.get_user_name_by_uid(uid)
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        get_or_set(&self.euid, &self.users, super::get_effective_uid)
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_effective_uid();
+        self.get_user_name_by_uid(uid)
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let username = username.as_ref();
+        let gids = self.memberships.get_or_insert(username, gid, || get_user_group_ids(username, gid))?;
+        Some(gids.into_iter().filter_map(|gid| self.get_group_by_gid(gid)).collect())
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let users = if self.list_cached { self.users.values() } else { self.load_users() };
+        Box::new(users.into_iter())
+    }
+}
+
+
+impl Groups for UsersCache {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.try_get_group_by_gid(gid).unwrap_or_default()
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.try_get_group_by_name(group_name).unwrap_or_default()
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        self.groups.get_or_insert_by_id(gid, || try_get_group_by_gid(gid))
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        let group_name = group_name.as_ref();
+        self.groups.get_or_insert_by_name(group_name, || try_get_group_by_name(group_name))
+    }
+
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        if self.names_only { self.bare_group_name(gid, || try_get_bare_group_by_gid(gid)) }
+                      else { self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc)) }
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        get_or_set(&self.gid, &self.groups, super::get_current_gid)
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_current_gid();
+        self.get_group_name_by_gid(gid)
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        get_or_set(&self.egid, &self.groups, super::get_effective_gid)
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_effective_gid();
+        self.get_group_name_by_gid(gid)
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let group = self.get_group_by_gid(gid)?;
+
+        let primary = |user: User| self.get_user_by_uid(user.uid()).unwrap_or_else(|| Arc::new(user));
+        Some(find_group_members(&group, |name| self.get_user_by_name(name), primary))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let groups = if self.list_cached { self.groups.values() } else { self.load_groups() };
+        Box::new(groups.into_iter())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::thread;
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<UsersCache>();
+    }
+
+    #[test]
+    fn same_arc_from_every_thread() {
+        let cache = Arc::new(UsersCache::new());
+        let uid = cache.get_current_uid();
+
+        let threads = (0 .. 8).map(|_| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.get_user_by_uid(uid).unwrap())
+        }).collect::<Vec<_>>();
+
+        let users = threads.into_iter().map(|t| t.join().unwrap()).collect::<Vec<_>>();
+        assert!(users.iter().all(|u| Arc::ptr_eq(u, &users[0])));
+    }
+
+    #[test]
+    fn name_then_uid() {
+        let cache = UsersCache::new();
+        let uid = cache.get_current_uid();
+        let name = get_user_name(uid);
+
+        let by_name = cache.get_user_by_name(&name).unwrap();
+        let by_uid = cache.get_user_by_uid(uid).unwrap();
+        assert!(Arc::ptr_eq(&by_name, &by_uid));
+    }
+
+    #[test]
+    fn negative_name() {
+        let cache = UsersCache::new();
+        assert!(cache.get_user_by_name("user\0").is_none());
+        assert!(cache.get_user_by_name("user\0").is_none());
+    }
+
+    #[test]
+    fn memberships() {
+        let cache = UsersCache::new().with_names_only();
+        let root = cache.get_user_by_uid(0).unwrap();
+
+        let groups = cache.get_user_groups(root.name(), root.primary_group_id()).unwrap();
+        let primary = groups.iter().find(|g| g.gid() == root.primary_group_id()).unwrap();
+        assert!(Arc::ptr_eq(primary, &cache.get_group_by_gid(root.primary_group_id()).unwrap()));
+
+        let members = cache.get_group_members(root.primary_group_id()).unwrap();
+        assert!(members.iter().any(|u| Arc::ptr_eq(u, &root)));
+    }
+
+    #[test]
+    fn memberships_cached() {
+        let cache = UsersCache::new();
+        let root = cache.get_user_by_uid(0).unwrap();
+        let lookup = || cache.get_user_groups(root.name(), root.primary_group_id()).unwrap();
+
+        let first = lookup();
+        assert!(Arc::ptr_eq(&first[0], &lookup()[0]));
+        assert_eq!(cache.stats().memberships, LookupStats { hits: 1, negative_hits: 0, misses: 1, libc_calls: 1 });
+        assert_eq!(cache.size().memberships, 1);
+
+        cache.invalidate_user(0);
+        lookup();
+        cache.invalidate_group(root.primary_group_id());
+        lookup();
+        cache.clear();
+        lookup();
+        assert_eq!(cache.stats().memberships.libc_calls, 4);
+    }
+
+    #[test]
+    fn memberships_expired() {
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(0));
+        let calls = Cell::new(0);
+        let lookup = || cache.memberships.get_or_insert(OsStr::new("fred"), 9001, || { calls.set(calls.get() + 1); Some(vec![ 9001 ]) });
+
+        assert_eq!(lookup(), Some(vec![ 9001 ]));
+        assert_eq!(lookup(), Some(vec![ 9001 ]));
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn members_with_iterator_alive() {
+        // This reads the users database under this crate’s own lock, which
+        // an iterator from `all_users` doesn’t hold.
+        let iter = unsafe { ::base::all_users() };
+        assert!(UsersCache::new().get_group_members(0).is_some());
+        drop(iter);
+    }
+
+    #[test]
+    fn listing() {
+        let cache = UsersCache::new();
+        let root = cache.all_users().find(|u| u.uid() == 0).unwrap();
+        assert!(Arc::ptr_eq(&root, &cache.get_user_by_uid(0).unwrap()));
+        assert!(cache.all_groups().any(|g| g.gid() == 0));
+        assert!(cache.size().groups > 0);
+    }
+
+    #[test]
+    fn cached_listing() {
+        let cache = UsersCache::new().with_cached_listing();
+        assert!(cache.get_user_by_uid(0).is_some());
+        assert!(cache.get_user_by_name("no-such-user\0").is_none());
+
+        let uids = cache.all_users().map(|u| u.uid()).collect::<Vec<_>>();
+        assert_eq!(uids, vec![ 0 ]);
+        assert_eq!(cache.all_groups().count(), 0);
+    }
+
+    #[test]
+    fn expired() {
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(0));
+        let uid = cache.get_current_uid();
+
+        let first = cache.get_user_by_uid(uid).unwrap();
+        let second = cache.get_user_by_uid(uid).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn not_expired() {
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(3600));
+        let uid = cache.get_current_uid();
+
+        let first = cache.get_user_by_uid(uid).unwrap();
+        let second = cache.get_user_by_uid(uid).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn invalidated() {
+        let cache = UsersCache::new();
+        let uid = cache.get_current_uid();
+        let name = get_user_name(uid);
+
+        let first = cache.get_user_by_uid(uid).unwrap();
+        cache.invalidate_user(uid);
+        let second = cache.get_user_by_name(&name).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+
+        cache.invalidate_user_name(&name);
+        let third = cache.get_user_by_uid(uid).unwrap();
+        assert!(!Arc::ptr_eq(&second, &third));
+    }
+
+    #[test]
+    fn cleared() {
+        let cache = UsersCache::new();
+        let gid = cache.get_current_gid();
+
+        let first = cache.get_group_by_gid(gid).unwrap();
+        cache.clear();
+        let second = cache.get_group_by_gid(gid).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let mut map = BiMap::new();
+        map.max_entries = Some(2);
+
+        map.insert(User::new(1, "one", 1));
+        map.insert(User::new(2, "two", 1));
+        map.get_by_id(1);
+        map.insert(User::new(3, "three", 1));
+
+        assert!(map.get_by_id(1).is_some());
+        assert!(map.get_by_id(2).is_none());
+        assert!(map.get_by_id(3).is_some());
+
+        assert!(map.get_by_name(OsStr::new("one")).is_some());
+        assert!(map.get_by_name(OsStr::new("two")).is_none());
+        assert_eq!(map.size().1, 2);
+    }
+
+    #[test]
+    fn negative_names_evicted() {
+        let mut map = BiMap::<Group>::new();
+        map.max_entries = Some(16);
+
+        for i in 0 .. 17 {
+            map.insert_by_name(OsStr::new(&format!("missing-{}", i)), None);
+        }
+
+        let (ids, names, _) = map.size();
+        assert_eq!(ids, 0);
+        assert_eq!(names, 14);
+        assert!(map.get_by_name(OsStr::new("missing-0")).is_none());
+        assert!(map.get_by_name(OsStr::new("missing-16")).unwrap().is_none());
+    }
+
+    #[test]
+    fn size() {
+        let cache = UsersCache::new();
+        assert_eq!(cache.size(), CacheSize::default());
+
+        cache.get_user_by_uid(cache.get_current_uid());
+        cache.get_group_by_name("no-such-group\0");
+
+        let size = cache.size();
+        assert_eq!((size.users, size.user_names, size.groups, size.group_names), (1, 1, 0, 1));
+        assert!(size.bytes > 0);
+    }
+
+    #[test]
+    fn preloaded() {
+        let cache = UsersCache::with_all_users_and_groups();
+        let root = cache.get_group_by_gid(0).unwrap();
+        cache.get_group_by_name(root.name());
+
+        let stats = cache.stats();
+        assert_eq!(stats.groups_by_id.libc_calls, 0);
+        assert_eq!(stats.groups_by_name.libc_calls, 0);
+    }
+
+    #[test]
+    fn preload_from_many_threads() {
+        let cache = Arc::new(UsersCache::new());
+
+        let threads = (0 .. 4).map(|_| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.preload_groups())
+        }).collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let (groups, _, _) = cache.groups.size();
+        assert_eq!(groups, read_all_groups().len());
+    }
+
+    /// Returns a cache containing some users and groups that don’t exist,
+    /// so the tests don’t depend on the system’s.
+    fn made_up_cache() -> UsersCache {
+        let cache = UsersCache::new();
+        cache.users.insert(User::new(9001, "fred", 9001).with_home_dir("/home/fred").with_shell("/bin/fish"));
+        cache.users.insert_by_id(9002, None);
+        cache.users.insert_by_name(OsStr::new("nobody-here"), None);
+        cache.groups.insert(Group::new(9001, "freds").add_member("fred").add_member("bob"));
+        cache
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut buf = Vec::new();
+        made_up_cache().write_to(&mut buf).unwrap();
+
+        let cache = UsersCache::new();
+        assert!(cache.read_from(&buf[..]).unwrap());
+
+        let fred = cache.get_user_by_name("fred").unwrap();
+        assert_eq!(fred.uid(), 9001);
+        assert_eq!(fred.home_dir(), Path::new("/home/fred"));
+        assert_eq!(fred.shell(), Path::new("/bin/fish"));
+        assert!(cache.get_user_by_uid(9002).is_none());
+        assert!(cache.get_user_by_name("nobody-here").is_none());
+
+        let freds = cache.get_group_by_gid(9001).unwrap();
+        assert_eq!(freds.members(), &[OsString::from("fred"), OsString::from("bob")]);
+
+        let stats = cache.stats();
+        assert_eq!(stats.users_by_id.libc_calls + stats.users_by_name.libc_calls + stats.groups_by_id.libc_calls, 0);
+    }
+
+    #[test]
+    fn load_keeps_existing() {
+        let mut buf = Vec::new();
+        made_up_cache().write_to(&mut buf).unwrap();
+
+        let cache = UsersCache::new();
+        cache.users.insert(User::new(9001, "fred", 1234));
+        cache.read_from(&buf[..]).unwrap();
+
+        assert_eq!(cache.get_user_by_uid(9001).unwrap().primary_group_id(), 1234);
+    }
+
+    #[test]
+    fn load_changed_database() {
+        let mut buf = Vec::new();
+        made_up_cache().write_to(&mut buf).unwrap();
+
+        let cache = UsersCache::new();
+        let changed = Some(UNIX_EPOCH + Duration::from_secs(1));
+        assert!(!cache.read_checked(&buf[..], changed, changed).unwrap());
+        assert_eq!(cache.size(), CacheSize::default());
+    }
+
+    #[test]
+    fn load_corrupt() {
+        let mut buf = Vec::new();
+        made_up_cache().write_to(&mut buf).unwrap();
+        buf.truncate(buf.len() - 3);
+
+        let cache = UsersCache::new();
+        assert!(cache.read_from(&buf[..]).is_err());
+        assert_eq!(cache.size(), CacheSize::default());
+
+        assert_eq!(cache.read_from(&b"not a cache file"[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn load_ages() {
+        let mut buf = Vec::new();
+        made_up_cache().write_to(&mut buf).unwrap();
+
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(0));
+        cache.read_from(&buf[..]).unwrap();
+        assert!(cache.users.get_by_id(9001).is_none());
+    }
+
+    #[test]
+    fn errors_not_cached() {
+        let cache = UsersCache::new();
+        let error = cache.users.get_or_insert_by_id(9001, || Err(io::Error::from_raw_os_error(libc::EIO)));
+        assert_eq!(error.unwrap_err().raw_os_error(), Some(libc::EIO));
+        assert_eq!(cache.size().users, 0);
+
+        let user = cache.users.get_or_insert_by_id(9001, || Ok(Some(User::new(9001, "fred", 9001))));
+        assert_eq!(user.unwrap().unwrap().name(), OsStr::new("fred"));
+        assert_eq!(cache.stats().users_by_id.libc_calls, 2);
+    }
+
+    #[test]
+    fn names_only() {
+        let cache = UsersCache::new().with_names_only();
+        assert_eq!(&*cache.get_user_name_by_uid(0).unwrap(), OsStr::new("root"));
+        assert_eq!(cache.get_user_name_by_uid(0), cache.get_user_name_by_uid(0));
+        assert!(cache.get_group_name_by_gid(0).is_some());
+        assert_eq!(cache.stats().users_by_id.libc_calls, 1);
+
+        // Only the name was cached, so the full user still gets looked up.
+        let root = cache.get_user_by_uid(0).unwrap();
+        assert_eq!(root.home_dir(), ::get_user_by_uid(0).unwrap().home_dir());
+        assert_ne!(root.home_dir(), Path::new(""));
+        assert_eq!(cache.stats().users_by_id.libc_calls, 2);
+    }
+
+    #[test]
+    fn names_only_errors_not_cached() {
+        let cache = UsersCache::new().with_names_only();
+        assert!(cache.bare_user_name(0, || Err(io::Error::from_raw_os_error(libc::EIO))).is_none());
+        assert!(cache.bare_group_name(0, || Err(io::Error::from_raw_os_error(libc::EIO))).is_none());
+        assert_eq!(cache.size().users, 0);
+        assert_eq!(cache.size().groups, 0);
+
+        assert!(cache.get_user_name_by_uid(0).is_some());
+        assert!(cache.get_group_name_by_gid(0).is_some());
+        assert_eq!(cache.stats().users_by_id.libc_calls, 2);
+        assert_eq!(cache.stats().groups_by_id.libc_calls, 2);
+    }
+
+    #[test]
+    fn names_only_preload() {
+        let cache = UsersCache::new().with_names_only();
+        cache.preload_users();
+        assert_eq!(cache.users.size().0, 0);
+        assert!(cache.bare_users.size().0 > 0);
+
+        assert!(cache.get_user_name_by_uid(0).is_some());
+        assert_eq!(cache.stats().users_by_id.libc_calls, 0);
+    }
+
+    #[test]
+    fn names_only_file() {
+        let full = UsersCache::new();
+        full.get_user_by_uid(0);
+        let mut buf = Vec::new();
+        full.write_to(&mut buf).unwrap();
+
+        let names_only = UsersCache::new().with_names_only();
+        assert!(!names_only.read_from(&buf[..]).unwrap());
+        assert!(UsersCache::new().read_from(&buf[..]).unwrap());
+    }
+
+    #[test]
+    fn stats() {
+        let cache = UsersCache::new();
+        let uid = cache.get_current_uid();
+
+        cache.get_user_by_uid(uid);
+        cache.get_user_by_uid(uid);
+        cache.get_group_by_name("no-such-group\0");
+        cache.get_group_by_name("no-such-group\0");
+        cache.get_group_by_name("no-such-group\0");
+
+        let stats = cache.stats();
+        assert_eq!(stats.users_by_id, LookupStats { hits: 1, negative_hits: 0, misses: 1, libc_calls: 1 });
+        assert_eq!(stats.groups_by_name, LookupStats { hits: 0, negative_hits: 2, misses: 1, libc_calls: 1 });
+        assert_eq!(stats.users_by_name, LookupStats::default());
+        assert_eq!(stats.groups_by_id, LookupStats::default());
+    }
+
+    #[test]
+    fn ids_after_switching() {
+        let cache = UsersCache::new();
+        let euid = cache.get_effective_uid();
+
+        // Pretend the cached value was read before switching to somebody else.
+        *lock(&cache.euid) = Some(CachedId { id: 31337, fetched: Instant::now(), generation: credentials_generation(), flushes: 0 });
+        assert_eq!(cache.get_effective_uid(), 31337);
+
+        ::switch::set_effective_uid(euid).unwrap();
+        assert_eq!(cache.get_effective_uid(), euid);
+    }
+
+    #[test]
+    fn ids_expire() {
+        let calls = Cell::new(0);
+        let read = || { calls.set(calls.get() + 1); 1001 };
+
+        let cache = UsersCache::new();
+        assert_eq!(get_or_set(&cache.uid, &cache.users, read), 1001);
+        assert_eq!(get_or_set(&cache.uid, &cache.users, read), 1001);
+        assert_eq!(calls.get(), 1);
+
+        // Emptying the map, as a watched file changing does, resets the ID.
+        cache.users.clear();
+        get_or_set(&cache.uid, &cache.users, read);
+        assert_eq!(calls.get(), 2);
+
+        let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(0));
+        get_or_set(&cache.gid, &cache.groups, read);
+        get_or_set(&cache.gid, &cache.groups, read);
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn saved_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = ::std::env::temp_dir().join(format!("users-save-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("users.cache");
+
+        let cache = UsersCache::new();
+        cache.get_user_by_uid(0);
+        cache.save(&path).unwrap();
+        cache.save(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Only the saved file is left behind, with no temporary files.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let loaded = UsersCache::new();
+        assert!(loaded.load(&path).unwrap());
+        assert_eq!(loaded.stats().users_by_id.libc_calls, 0);
+        assert!(loaded.get_user_by_uid(0).is_some());
+        assert_eq!(loaded.stats().users_by_id.libc_calls, 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_watch() {
+        let path = ::std::env::temp_dir().join(format!("users-watch-{}", ::std::process::id()));
+        fs::write(&path, "root:x:0:0::/root:/bin/sh\n").unwrap();
+
+        let watch = FileWatch::new(&path);
+        assert!(!watch.has_changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watch.has_changed());
+        assert!(!watch.has_changed());
+    }
+
+    fn get_user_name(uid: uid_t) -> Arc<OsStr> {
+        Arc::clone(&::base::get_user_by_uid(uid).unwrap().name_arc)
     }
 }
@@ -0,0 +1,347 @@
+//! Stacking producers of users and groups on top of each other.
+//!
+//! A [`ChainedProvider`](struct.ChainedProvider.html) asks each of a list
+//! of producers in turn, the same way the C library works through the
+//! sources listed in `/etc/nsswitch.conf`. For example, a table of
+//! overrides could come first, followed by the system’s own users:
+//!
+//! ```
+//! use users::{Users, UsersCache};
+//! use users::chain::{ChainedProvider, NotFound};
+//! use users::mock::{MockUsers, User};
+//!
+//! let mut overrides = MockUsers::with_current_uid(0);
+//! overrides.add_user(User::new(0, "admin", 0));
+//!
+//! let chain = ChainedProvider::new(overrides)
+//!     .with_layer(UsersCache::new());
+//!
+//! assert_eq!(chain.get_user_by_uid(0).unwrap().name(), "admin");
+//! ```
+//!
+//! A layer followed by `on_not_found(NotFound::Return)` stops the search
+//! if it doesn’t have the user or group being looked up, like
+//! `[NOTFOUND=return]` does in `nsswitch.conf`, so the layers after it only
+//! get asked when it fails.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io;
+use std::sync::Arc;
+
+use libc::{uid_t, gid_t};
+
+use base::{User, Group};
+use traits::{Users, Groups, DynProvider};
+
+
+/// What a `ChainedProvider` does when a layer doesn’t have the user or group
+/// being looked up.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum NotFound {
+
+    /// Ask the next layer, which is the default.
+    Continue,
+
+    /// Stop the search, and return that there’s no such user or group.
+    Return,
+}
+
+/// A producer of users and groups that asks each of several other producers
+/// in turn, returning the first answer it gets.
+///
+/// Lookups of users and groups by ID or name go down the layers in the order
+/// they were added, until one has the user or group, or until one that
+/// doesn’t have it is set to [`NotFound::Return`](enum.NotFound.html).
+/// Layers that fail, which only the `try_get_*` methods can tell apart from
+/// not finding anything, are always skipped; if every layer that was asked
+/// failed or didn’t have the user or group, the last error gets returned.
+///
+/// The other methods work like this:
+///
+/// - The current and effective user and group IDs come from the first layer,
+///   and their names get looked up down the chain.
+/// - A user’s groups, and a group’s members, come from the first layer that
+///   has that user or group.
+/// - Listing every user or group lists the ones in every layer, leaving out
+///   any with the same ID as one in an earlier layer, which that one hides.
+pub struct ChainedProvider {
+    layers: Vec<Layer>,
+}
+
+/// One of the producers in a `ChainedProvider`.
+struct Layer {
+    provider: Box<dyn DynProvider + Send + Sync>,
+    not_found: NotFound,
+}
+
+impl ChainedProvider {
+
+    /// Creates a chain with the given producer as its first layer.
+    pub fn new<P: DynProvider + Send + Sync + 'static>(provider: P) -> Self {
+        Self { layers: Vec::new() }.with_layer(provider)
+    }
+
+    /// Adds the given producer as the last layer, to be asked after the
+    /// others.
+    pub fn with_layer<P: DynProvider + Send + Sync + 'static>(mut self, provider: P) -> Self {
+        self.layers.push(Layer { provider: Box::new(provider), not_found: NotFound::Continue });
+        self
+    }
+
+    /// Sets what happens when the most recently added layer doesn’t have the
+    /// user or group being looked up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{Users, UsersCache};
+    /// use users::chain::{ChainedProvider, NotFound};
+    /// use users::mock::MockUsers;
+    ///
+    /// // Only ever look at the mock table, which has no users.
+    /// let chain = ChainedProvider::new(MockUsers::with_current_uid(0))
+    ///     .on_not_found(NotFound::Return)
+    ///     .with_layer(UsersCache::new());
+    ///
+    /// assert!(chain.get_user_by_uid(0).is_none());
+    /// ```
+    pub fn on_not_found(mut self, action: NotFound) -> Self {
+        if let Some(layer) = self.layers.last_mut() {
+            layer.not_found = action;
+        }
+
+        self
+    }
+
+    /// Asks each layer in turn, until one finds something, one that doesn’t
+    /// is set to return, or there are no more. If nothing was found, the
+    /// last error from a layer that was asked gets returned.
+    fn search<'a, T, F>(&'a self, lookup: F) -> io::Result<Option<T>>
+    where F: Fn(&'a (dyn DynProvider + Send + Sync)) -> io::Result<Option<T>>,
+    {
+        let mut error = None;
+
+        for layer in &self.layers {
+            match lookup(&*layer.provider) {
+                Ok(Some(found)) => return Ok(Some(found)),
+                Ok(None) if layer.not_found == NotFound::Return => break,
+                Ok(None) => {},
+                Err(e) => error = Some(e),
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None    => Ok(None),
+        }
+    }
+
+    fn first(&self) -> &(dyn DynProvider + Send + Sync) {
+        &*self.layers[0].provider
+    }
+}
+
+impl Users for ChainedProvider {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.try_get_user_by_uid(uid).unwrap_or_default()
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.try_get_user_by_name(username).unwrap_or_default()
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.first().get_current_uid()
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_name_by_uid(self.get_current_uid())
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.first().get_effective_uid()
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_name_by_uid(self.get_effective_uid())
+    }
+
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        self.search(|p| Ok(p.get_user_name_by_uid(uid))).unwrap_or_default()
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        self.search(|p| p.try_get_user_by_uid(uid))
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        self.search(|p| p.try_get_user_by_name(username))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let found = self.search(|p| Ok(p.get_user_by_name(username).map(|_| p))).unwrap_or_default()?;
+        found.get_user_groups(username, gid)
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let mut seen = HashSet::new();
+        let users = self.layers.iter()
+                        .flat_map(|layer| layer.provider.all_users())
+                        .filter(|u| seen.insert(u.uid()))
+                        .collect::<Vec<_>>();
+        Box::new(users.into_iter())
+    }
+}
+
+impl Groups for ChainedProvider {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.try_get_group_by_gid(gid).unwrap_or_default()
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.try_get_group_by_name(group_name).unwrap_or_default()
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.first().get_current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_name_by_gid(self.get_current_gid())
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.first().get_effective_gid()
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_name_by_gid(self.get_effective_gid())
+    }
+
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        self.search(|p| Ok(p.get_group_name_by_gid(gid))).unwrap_or_default()
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        self.search(|p| p.try_get_group_by_gid(gid))
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        self.search(|p| p.try_get_group_by_name(group_name))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let found = self.search(|p| Ok(p.get_group_by_gid(gid).map(|_| p))).unwrap_or_default()?;
+        found.get_group_members(gid)
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let mut seen = HashSet::new();
+        let groups = self.layers.iter()
+                         .flat_map(|layer| layer.provider.all_groups())
+                         .filter(|g| seen.insert(g.gid()))
+                         .collect::<Vec<_>>();
+        Box::new(groups.into_iter())
+    }
+}
+
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use super::*;
+    use base::os::unix::GroupExt;
+    use mock::{MockUsers, Lookup};
+
+    fn layers() -> (MockUsers, MockUsers) {
+        let mut first = MockUsers::with_current_uid(1000).with_current_gid(100);
+        first.add_user(User::new(1000, "fred", 100));
+        first.add_group(Group::new(100, "staff"));
+
+        let mut second = MockUsers::with_current_uid(0);
+        second.add_user(User::new(1000, "frederick", 100));
+        second.add_user(User::new(1001, "bob", 101));
+        second.add_group(Group::new(101, "bobs").add_member("fred"));
+
+        (first, second)
+    }
+
+    fn uids<I: Iterator<Item=Arc<User>>>(users: I) -> Vec<uid_t> {
+        users.map(|u| u.uid()).collect()
+    }
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<ChainedProvider>();
+    }
+
+    #[test]
+    fn falls_through() {
+        let (first, second) = layers();
+        let chain = ChainedProvider::new(first).with_layer(second);
+
+        assert_eq!(chain.get_user_by_uid(1000).unwrap().name(), "fred");
+        assert_eq!(chain.get_user_by_uid(1001).unwrap().name(), "bob");
+        assert_eq!(chain.get_user_by_name("frederick").unwrap().uid(), 1000);
+        assert_eq!(&*chain.get_group_name_by_gid(101).unwrap(), "bobs");
+        assert!(chain.get_user_by_uid(1002).is_none());
+
+        assert_eq!(chain.get_current_uid(), 1000);
+        assert_eq!(&*chain.get_current_groupname().unwrap(), "staff");
+    }
+
+    #[test]
+    fn not_found_return() {
+        let (first, second) = layers();
+        let chain = ChainedProvider::new(first).on_not_found(NotFound::Return).with_layer(second);
+
+        assert!(chain.get_user_by_uid(1000).is_some());
+        assert!(chain.get_user_by_uid(1001).is_none());
+        assert!(chain.get_group_by_gid(101).is_none());
+    }
+
+    #[test]
+    fn errors_skipped() {
+        let (mut first, second) = layers();
+        first.fail_lookup(Lookup::UserByUid(1000), io::ErrorKind::TimedOut);
+        first.fail_lookup(Lookup::UserByUid(1002), io::ErrorKind::TimedOut);
+        let chain = ChainedProvider::new(first).on_not_found(NotFound::Return).with_layer(second);
+
+        assert_eq!(chain.get_user_by_uid(1000).unwrap().name(), "frederick");
+        assert_eq!(chain.try_get_user_by_uid(1002).unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn error_before_return() {
+        let (mut first, second) = layers();
+        first.fail_lookup(Lookup::UserByUid(1001), io::ErrorKind::TimedOut);
+        let chain = ChainedProvider::new(first)
+            .with_layer(MockUsers::with_current_uid(0)).on_not_found(NotFound::Return)
+            .with_layer(second);
+
+        assert_eq!(chain.try_get_user_by_uid(1001).unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(chain.try_get_user_by_uid(1002).unwrap().is_none());
+    }
+
+    #[test]
+    fn memberships() {
+        let (first, second) = layers();
+        let chain = ChainedProvider::new(first).with_layer(second);
+
+        let gids = chain.get_user_groups("fred", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 100 ]);
+        assert_eq!(uids(chain.get_group_members(101).unwrap().into_iter()), vec![ 1001 ]);
+    }
+
+    #[test]
+    fn listing() {
+        let (first, second) = layers();
+        let chain = ChainedProvider::new(first).with_layer(second);
+
+        let users = chain.all_users().collect::<Vec<_>>();
+        assert_eq!(uids(users.iter().cloned()), vec![ 1000, 1001 ]);
+        assert_eq!(users[0].name(), "fred");
+        assert_eq!(chain.all_groups().count(), 2);
+    }
+}
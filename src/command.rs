@@ -0,0 +1,204 @@
+//! Running child processes as another user.
+//!
+//! The standard library’s
+//! [`CommandExt`](https://doc.rust-lang.org/std/os/unix/process/trait.CommandExt.html)
+//! trait can set the user and group IDs of a child process, but it cannot
+//! set its supplementary groups, and it knows nothing about the environment
+//! a user expects to have. The [`UserCommandExt`](trait.UserCommandExt.html)
+//! trait in this module fills in the gaps, configuring a `Command` from a
+//! `User` value.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use users::get_user_by_name;
+//! use users::command::UserCommandExt;
+//!
+//! let user = get_user_by_name("stevedore").expect("No such user");
+//! let status = Command::new("id")
+//!     .as_user(&user).expect("Failed to look up groups")
+//!     .status().expect("Failed to run command");
+//! ```
+//!
+//! Typically, running a process as anyone other than the user already
+//! running the current one requires root privileges.
+
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use libc::{uid_t, gid_t};
+
+use base::{User, get_user_group_ids};
+use base::os::unix::UserExt;
+use login::LoginEnvironment;
+use switch::{set_current_uid, set_current_gid};
+
+
+/// Extensions to `std::process::Command` for running a process as another
+/// user.
+pub trait UserCommandExt {
+
+    /// Configures the command to run as the given user, like `su` does.
+    ///
+    /// This sets the child’s user ID, its group ID to the user’s primary
+    /// group, and its supplementary groups to the ones the user is a member
+    /// of. It also sets the `HOME`, `USER`, `LOGNAME`, and `SHELL` environment
+    /// variables, and starts the child in the user’s home directory. The rest
+    /// of the environment is inherited, as usual.
+    ///
+    /// Any of these can be overridden by calling the relevant `Command`
+    /// method afterwards, such as `current_dir`.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getgrouplist`](https://docs.rs/libc/*/libc/fn.getgrouplist.html)
+    /// - [`setgroups`](https://docs.rs/libc/*/libc/fn.setgroups.html)
+    /// - [`setgid`](https://docs.rs/libc/*/libc/fn.setgid.html)
+    /// - [`setuid`](https://docs.rs/libc/*/libc/fn.setuid.html)
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if the user’s groups cannot be looked
+    /// up. Failing to switch to the user happens in the child process, so it
+    /// gets reported when the command is spawned instead.
+    fn as_user(&mut self, user: &User) -> io::Result<&mut Command>;
+
+    /// Configures the command to run as the given user in a fresh login
+    /// environment, like `su -` does.
+    ///
+    /// As well as doing everything [`as_user`](#tymethod.as_user) does, this
+    /// replaces the environment with the one built by
+    /// [`LoginEnvironment`](../login/struct.LoginEnvironment.html), keeping
+    /// only `TERM` from the current one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if the user’s groups cannot be looked
+    /// up, or if an I/O error occurs while reading `/etc/login.defs`.
+    fn as_login_user(&mut self, user: &User) -> io::Result<&mut Command>;
+}
+
+impl UserCommandExt for Command {
+    fn as_user(&mut self, user: &User) -> io::Result<&mut Command> {
+        let uid = user.uid();
+        let gid = user.primary_group_id();
+
+        let groups = match get_user_group_ids(user.name(), gid) {
+            Some(gs) => gs,
+            None     => return Err(io::Error::new(io::ErrorKind::NotFound, "Failed to look up user’s groups")),
+        };
+
+        set_environment(self, user);
+
+        // The standard library’s `uid` and `gid` methods are not used here:
+        // they change the user before any `pre_exec` closures get run, after
+        // which it’s too late to change the supplementary groups.
+        unsafe {
+            self.pre_exec(move || become_user(uid, gid, &groups));
+        }
+
+        Ok(self)
+    }
+
+    fn as_login_user(&mut self, user: &User) -> io::Result<&mut Command> {
+        set_login_environment(self, user)?;
+        self.as_user(user)
+    }
+}
+
+
+/// Sets the environment variables and working directory that `as_user`
+/// sets, without touching the user or groups.
+fn set_environment(command: &mut Command, user: &User) {
+    command.env("HOME",    user.home_dir())
+           .env("USER",    user.name())
+           .env("LOGNAME", user.name())
+           .env("SHELL",   user.shell())
+           .current_dir(user.home_dir());
+}
+
+/// Replaces the command’s environment with a fresh login environment for
+/// the given user.
+fn set_login_environment(command: &mut Command, user: &User) -> io::Result<()> {
+    let env = LoginEnvironment::new(user)
+        .with_preserved("TERM")
+        .build()?;
+
+    command.env_clear().envs(env);
+    Ok(())
+}
+
+
+/// Switches the current process to the given supplementary groups, group,
+/// and user, in that order: changing the user drops the privileges needed to
+/// change the others, so it has to come last.
+///
+/// This gets run in the child process between `fork` and `exec`, so it must
+/// not allocate.
+#[allow(trivial_numeric_casts)]  // the count is a `c_int` on macOS
+fn become_user(uid: uid_t, gid: gid_t, groups: &[gid_t]) -> io::Result<()> {
+    if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    set_current_gid(gid)?;
+    set_current_uid(uid)?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    // These tests run the command as the current user, leaving out the
+    // `pre_exec` closure that switches users, as that needs root.
+
+    fn test_user() -> User {
+        User::new(1337, "stevedore", 1337)
+            .with_home_dir(&env::temp_dir())
+            .with_shell("/bin/false")
+    }
+
+    fn run(command: &mut Command) -> String {
+        let output = command.output().expect("Failed to run sh");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn environment() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo $HOME $USER $LOGNAME $SHELL");
+        set_environment(&mut command, &test_user());
+
+        let expected = format!("{} stevedore stevedore /bin/false\n", env::temp_dir().display());
+        assert_eq!(run(&mut command), expected);
+    }
+
+    #[test]
+    fn working_directory() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("pwd -P");
+        set_environment(&mut command, &test_user());
+
+        let expected = env::temp_dir().canonicalize().unwrap();
+        assert_eq!(run(&mut command), format!("{}\n", expected.display()));
+    }
+
+    #[test]
+    fn login_environment() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo ${USERS_TEST_LEFTOVER-unset} $HOME")
+               .env("USERS_TEST_LEFTOVER", "set");
+
+        let user = test_user();
+        set_login_environment(&mut command, &user).unwrap();
+        set_environment(&mut command, &user);
+
+        let expected = format!("unset {}\n", env::temp_dir().display());
+        assert_eq!(run(&mut command), expected);
+    }
+}
@@ -83,10 +83,28 @@
 //! println!("Hello again, {}!", user.name().to_string_lossy());
 //! ```
 //!
-//! This cache is **only additive**: it’s not possible to drop it, or erase
-//! selected entries, as when the database may have been modified, it’s best to
-//! start entirely afresh. So to accomplish this, just start using a new
-//! `UsersCache`.
+//! By default, this cache holds on to every entry forever. For long-running
+//! programs that need to notice when the database gets modified, entries can
+//! be given a time-to-live, selected entries can be invalidated, or the whole
+//! cache can be cleared:
+//!
+//! ```
+//! use std::time::Duration;
+//! use users::{Users, UsersCache};
+//!
+//! let cache = UsersCache::new().with_positive_ttl(Duration::from_secs(60));
+//! let uid = cache.get_current_uid();
+//! cache.invalidate_user(uid);
+//! cache.clear();
+//! ```
+//!
+//! The cache can also be limited to a number of entries with
+//! `with_max_entries`, after which the least recently used ones get thrown
+//! away.
+//!
+//! For a consistent view of the whole database, the
+//! [`UsersSnapshot`](snapshot/struct.UsersSnapshot.html) type reads every user
+//! and group at once into an immutable table.
 //!
 //!
 //! ## Groups
@@ -111,7 +129,9 @@
 //! ## Logging
 //!
 //! The `logging` feature, which is on by default, uses the `log` crate to
-//! record all interactions with the operating system.
+//! record all interactions with the operating system. Lookups also record
+//! how long they took, and ones that take longer than a tenth of a second get
+//! logged at Debug level, so slow name service backends can be spotted.
 //!
 //!
 //! ## Caveats
@@ -136,7 +156,7 @@ pub use base::{get_effective_uid, get_effective_username};
 pub use base::{get_current_gid, get_current_groupname};
 pub use base::{get_effective_gid, get_effective_groupname};
 pub use base::{get_user_groups, group_access_list};
-pub use base::{all_users};
+pub use base::{all_users, all_groups};
 
 #[cfg(feature = "cache")]
 pub mod cache;
@@ -144,10 +164,34 @@ pub mod cache;
 #[cfg(feature = "cache")]
 pub use cache::UsersCache;
 
+#[cfg(feature = "cache")]
+pub mod snapshot;
+
+#[cfg(feature = "cache")]
+pub use snapshot::UsersSnapshot;
+
+#[cfg(feature = "cache")]
+pub mod refresh;
+
 #[cfg(feature = "mock")]
 pub mod mock;
 
 pub mod switch;
 
+pub mod command;
+
+pub mod login;
+
+pub mod privilege;
+
 mod traits;
-pub use traits::{Users, Groups};
+pub use traits::{Users, Groups, DynUsers, DynGroups, DynProvider};
+
+mod system;
+pub use system::SystemUsers;
+
+pub mod chain;
+pub use chain::ChainedProvider;
+
+pub mod overlay;
+pub use overlay::Overlay;
@@ -0,0 +1,336 @@
+//! Building the environment of a login session.
+//!
+//! When a user logs in, or when `su -` starts a fresh session, the new
+//! process doesn’t inherit the environment of the one that started it:
+//! instead, it gets a handful of variables that describe the user, such as
+//! `HOME` and `SHELL`, along with a `PATH` taken from the system’s
+//! `/etc/login.defs` file. The [`LoginEnvironment`](struct.LoginEnvironment.html)
+//! type builds this set of variables for a `User`, so a process can be
+//! started with it without having to go through a shell.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use users::get_user_by_name;
+//! use users::login::LoginEnvironment;
+//!
+//! let user = get_user_by_name("stevedore").expect("No such user");
+//! let env = LoginEnvironment::new(&user)
+//!     .with_preserved("TERM")
+//!     .build()
+//!     .expect("Failed to read login.defs");
+//!
+//! Command::new("printenv").env_clear().envs(env).status().unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base::User;
+use base::os::unix::UserExt;
+
+
+/// The `PATH` for normal users when `login.defs` doesn’t specify one.
+const DEFAULT_PATH: &str = "/usr/local/bin:/bin:/usr/bin";
+
+/// The `PATH` for root when `login.defs` doesn’t specify one.
+const DEFAULT_SUPATH: &str = "/usr/local/sbin:/usr/local/bin:/sbin:/bin:/usr/sbin:/usr/bin";
+
+/// The mail spool directory when `login.defs` doesn’t specify one.
+const DEFAULT_MAIL_DIR: &str = "/var/mail";
+
+/// The directory containing each user’s runtime directory.
+const RUNTIME_DIR_BASE: &str = "/run/user";
+
+
+/// The settings from the `/etc/login.defs` file that affect a login
+/// session’s environment.
+///
+/// Any settings missing from the file take the same default values as
+/// `login` and `su` use.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoginDefs {
+    env_path: Option<OsString>,
+    env_supath: Option<OsString>,
+    mail_dir: Option<PathBuf>,
+    mail_file: Option<PathBuf>,
+}
+
+impl LoginDefs {
+
+    /// Reads the system’s `/etc/login.defs` file. A missing file is not an
+    /// error, as every setting has a default value.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error other than the file
+    /// not being found occurs while reading it.
+    pub fn read() -> io::Result<Self> {
+        match Self::read_from("/etc/login.defs") {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            result => result,
+        }
+    }
+
+    /// Reads the `login.defs` file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while reading
+    /// the file.
+    pub fn read_from<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Ok(Self::parse(&text))
+    }
+
+    /// Parses the text of a `login.defs` file. Lines that aren’t settings
+    /// this type knows about are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::ffi::OsStr;
+    /// use users::login::LoginDefs;
+    ///
+    /// let defs = LoginDefs::parse("ENV_PATH  PATH=/usr/bin:/bin\n");
+    /// assert_eq!(defs.env_path(), OsStr::new("/usr/bin:/bin"));
+    /// ```
+    pub fn parse(text: &str) -> Self {
+        let mut defs = Self::default();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("").trim().trim_matches('"');
+            if value.is_empty() {
+                continue;
+            }
+
+            match key {
+                "ENV_PATH"   => defs.env_path   = Some(strip_path_prefix(value).into()),
+                "ENV_SUPATH" => defs.env_supath = Some(strip_path_prefix(value).into()),
+                "MAIL_DIR"   => defs.mail_dir   = Some(value.into()),
+                "MAIL_FILE"  => defs.mail_file  = Some(value.into()),
+                _            => {},
+            }
+        }
+
+        defs
+    }
+
+    /// Returns the `PATH` for users other than root.
+    pub fn env_path(&self) -> &OsStr {
+        self.env_path.as_ref().map_or(OsStr::new(DEFAULT_PATH), |p| p.as_os_str())
+    }
+
+    /// Returns the `PATH` for root.
+    pub fn env_supath(&self) -> &OsStr {
+        self.env_supath.as_ref().map_or(OsStr::new(DEFAULT_SUPATH), |p| p.as_os_str())
+    }
+
+    /// Returns the path to the given user’s mailbox. This is either in the
+    /// mail spool directory, or in their home directory if `MAIL_FILE` is
+    /// set.
+    pub fn mailbox(&self, user: &User) -> PathBuf {
+        match self.mail_file {
+            Some(ref file) => user.home_dir().join(file),
+            None => {
+                let dir = self.mail_dir.as_ref().map_or(Path::new(DEFAULT_MAIL_DIR), |d| d.as_path());
+                dir.join(user.name())
+            }
+        }
+    }
+}
+
+/// The `ENV_PATH` and `ENV_SUPATH` settings can be written either as a path,
+/// or as an assignment to `PATH`.
+fn strip_path_prefix(value: &str) -> &str {
+    value.trim_start_matches("PATH=")
+}
+
+
+/// A builder for the environment variables of a login session.
+///
+/// The environment contains these variables:
+///
+/// - **HOME:** the user’s home directory
+/// - **SHELL:** the user’s shell
+/// - **USER** and **LOGNAME:** the user’s name
+/// - **PATH:** `ENV_SUPATH` from `login.defs` for root, or `ENV_PATH` for
+///   anyone else
+/// - **MAIL:** the user’s mailbox
+/// - **XDG_RUNTIME_DIR:** the user’s runtime directory, if one exists
+///
+/// Variables from the current process’s environment can be carried across
+/// with [`with_preserved`](#method.with_preserved), but the ones above always
+/// take the values for the new session.
+#[derive(Debug)]
+pub struct LoginEnvironment<'user> {
+    user: &'user User,
+    defs: Option<LoginDefs>,
+    preserved: Vec<OsString>,
+}
+
+impl<'user> LoginEnvironment<'user> {
+
+    /// Creates a new builder for the given user’s login environment.
+    pub fn new(user: &'user User) -> Self {
+        Self { user, defs: None, preserved: Vec::new() }
+    }
+
+    /// Uses the given `login.defs` settings, instead of reading them from
+    /// the system.
+    pub fn with_login_defs(mut self, defs: LoginDefs) -> Self {
+        self.defs = Some(defs);
+        self
+    }
+
+    /// Carries the variable with the given name across from the current
+    /// process’s environment, if it is set.
+    pub fn with_preserved<S: AsRef<OsStr> + ?Sized>(mut self, name: &S) -> Self {
+        self.preserved.push(name.into());
+        self
+    }
+
+    /// Builds the environment, returning a map of variable names to values.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while reading
+    /// `/etc/login.defs`.
+    pub fn build(self) -> io::Result<BTreeMap<OsString, OsString>> {
+        let defs = match self.defs {
+            Some(ref defs) => defs.clone(),
+            None           => LoginDefs::read()?,
+        };
+
+        let runtime_dir = Path::new(RUNTIME_DIR_BASE).join(self.user.uid().to_string());
+        let runtime_dir = if runtime_dir.is_dir() { Some(runtime_dir) } else { None };
+
+        Ok(self.build_with(&defs, runtime_dir, |name| env::var_os(name)))
+    }
+
+    fn build_with<F>(&self, defs: &LoginDefs, runtime_dir: Option<PathBuf>, getenv: F) -> BTreeMap<OsString, OsString>
+    where F: Fn(&OsStr) -> Option<OsString>
+    {
+        let mut vars = BTreeMap::new();
+
+        for name in &self.preserved {
+            if let Some(value) = getenv(name) {
+                vars.insert(name.clone(), value);
+            }
+        }
+
+        let user = self.user;
+        let path = if user.uid() == 0 { defs.env_supath() } else { defs.env_path() };
+
+        vars.insert("HOME".into(),    user.home_dir().into());
+        vars.insert("SHELL".into(),   user.shell().into());
+        vars.insert("USER".into(),    user.name().into());
+        vars.insert("LOGNAME".into(), user.name().into());
+        vars.insert("PATH".into(),    path.into());
+        vars.insert("MAIL".into(),    defs.mailbox(user).into());
+
+        if let Some(dir) = runtime_dir {
+            vars.insert("XDG_RUNTIME_DIR".into(), dir.into());
+        }
+
+        vars
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stevedore() -> User {
+        User::new(501, "stevedore", 100)
+            .with_home_dir("/home/stevedore")
+            .with_shell("/bin/zsh")
+    }
+
+    fn get(vars: &BTreeMap<OsString, OsString>, name: &str) -> Option<String> {
+        vars.get(OsStr::new(name)).map(|v| v.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn parse_defs() {
+        let defs = LoginDefs::parse("# comment\n\
+                                     MAIL_DIR        /var/spool/mail\n\
+                                     ENV_SUPATH\tPATH=/sbin:/bin\n\
+                                     ENV_PATH        /bin\n\
+                                     UMASK           022\n");
+
+        assert_eq!(defs.env_path(), OsStr::new("/bin"));
+        assert_eq!(defs.env_supath(), OsStr::new("/sbin:/bin"));
+        assert_eq!(defs.mailbox(&stevedore()), Path::new("/var/spool/mail/stevedore"));
+    }
+
+    #[test]
+    fn default_defs() {
+        let defs = LoginDefs::parse("");
+        assert_eq!(defs.env_path(), OsStr::new(DEFAULT_PATH));
+        assert_eq!(defs.env_supath(), OsStr::new(DEFAULT_SUPATH));
+        assert_eq!(defs.mailbox(&stevedore()), Path::new("/var/mail/stevedore"));
+    }
+
+    #[test]
+    fn mail_file() {
+        let defs = LoginDefs::parse("MAIL_FILE .mail\n");
+        assert_eq!(defs.mailbox(&stevedore()), Path::new("/home/stevedore/.mail"));
+    }
+
+    #[test]
+    fn environment() {
+        let user = stevedore();
+        let defs = LoginDefs::parse("ENV_PATH PATH=/usr/bin:/bin\n");
+        let vars = LoginEnvironment::new(&user)
+            .build_with(&defs, Some("/run/user/501".into()), |_| None);
+
+        assert_eq!(get(&vars, "HOME"),            Some("/home/stevedore".into()));
+        assert_eq!(get(&vars, "SHELL"),           Some("/bin/zsh".into()));
+        assert_eq!(get(&vars, "USER"),            Some("stevedore".into()));
+        assert_eq!(get(&vars, "LOGNAME"),         Some("stevedore".into()));
+        assert_eq!(get(&vars, "PATH"),            Some("/usr/bin:/bin".into()));
+        assert_eq!(get(&vars, "MAIL"),            Some("/var/mail/stevedore".into()));
+        assert_eq!(get(&vars, "XDG_RUNTIME_DIR"), Some("/run/user/501".into()));
+        assert_eq!(vars.len(), 7);
+    }
+
+    #[test]
+    fn root_path() {
+        let user = User::new(0, "root", 0);
+        let defs = LoginDefs::parse("ENV_SUPATH PATH=/sbin:/bin\nENV_PATH PATH=/bin\n");
+        let vars = LoginEnvironment::new(&user).build_with(&defs, None, |_| None);
+
+        assert_eq!(get(&vars, "PATH"), Some("/sbin:/bin".into()));
+        assert_eq!(get(&vars, "XDG_RUNTIME_DIR"), None);
+    }
+
+    #[test]
+    fn preserved() {
+        let user = stevedore();
+        let vars = LoginEnvironment::new(&user)
+            .with_preserved("TERM")
+            .with_preserved("HOME")
+            .with_preserved("LANG")
+            .build_with(&LoginDefs::default(), None, |name| {
+                if name == "LANG" { None } else { Some("preserved".into()) }
+            });
+
+        assert_eq!(get(&vars, "TERM"), Some("preserved".into()));
+        assert_eq!(get(&vars, "HOME"), Some("/home/stevedore".into()));
+        assert_eq!(get(&vars, "LANG"), None);
+    }
+}
@@ -29,6 +29,86 @@
 //!
 //! The exports get re-exported into the mock module, for simpler `use` lines.
 //!
+//! Tables can also be read from text in the same format as the
+//! `/etc/passwd` and `/etc/group` files, so fixtures can be copied from a
+//! real system:
+//!
+//! ```
+//! use users::Users;
+//! use users::mock::MockUsers;
+//!
+//! let users = MockUsers::from_passwd_and_group(
+//!     "root:x:0:0:root:/root:/bin/bash\n\
+//!      fred:x:1000:1000:Fred:/home/fred:/bin/zsh\n",
+//!     "root:x:0:\n\
+//!      fred:x:1000:\n",
+//! ).unwrap();
+//!
+//! assert_eq!(users.get_user_by_name("fred").unwrap().uid(), 1000);
+//! ```
+//!
+//! The effective user ID, and the current and effective group IDs, start out
+//! the same as the current user ID, but can each be changed, to test code that
+//! behaves differently when running setuid or with a different primary group:
+//!
+//! ```
+//! use users::{Users, Groups};
+//! use users::mock::MockUsers;
+//!
+//! let users = MockUsers::with_current_uid(1000)
+//!     .with_effective_uid(0)
+//!     .with_current_gid(100)
+//!     .with_effective_gid(100);
+//!
+//! assert_eq!(users.get_current_uid(), 1000);
+//! assert_eq!(users.get_effective_uid(), 0);
+//! assert_eq!(users.get_current_gid(), 100);
+//! ```
+//!
+//!
+//! For fixtures with lots of group memberships, the
+//! [`builder`](struct.MockUsers.html#method.builder) method starts a
+//! [`MockUsersBuilder`](struct.MockUsersBuilder.html), which creates the
+//! groups that users are put in and fills in their members:
+//!
+//! ```
+//! use users::{Users, Groups};
+//! use users::mock::MockUsers;
+//! use users::os::unix::GroupExt;
+//!
+//! let users = MockUsers::builder()
+//!     .user("alice", 1000).in_groups(&["wheel", "docker"]).home("/home/alice")
+//!     .user("bob", 1001).in_groups(&["docker"])
+//!     .group("wheel", 10)
+//!     .current("alice")
+//!     .build();
+//!
+//! let docker = users.get_group_by_name("docker").unwrap();
+//! assert_eq!(docker.members().len(), 2);
+//! assert_eq!(users.get_current_gid(), 1000);
+//! ```
+//!
+//!
+//! ## Recording Lookups
+//!
+//! To check which lookups reach a table — for example, to make sure that a
+//! caching layer doesn’t ask for the same user twice — wrap it in a
+//! [`RecordingUsers`](struct.RecordingUsers.html), which remembers every call
+//! made through it:
+//!
+//! ```
+//! use users::{Users, User};
+//! use users::mock::{MockUsers, RecordingUsers};
+//!
+//! let mut users = MockUsers::with_current_uid(1000);
+//! users.add_user(User::new(1000, "fred", 1000));
+//!
+//! let recorder = RecordingUsers::new(users);
+//! recorder.get_user_by_uid(1000);
+//! recorder.assert_looked_up_uid(1000, 1);
+//! recorder.assert_looked_up_username("fred", 0);
+//! ```
+//!
 //!
 //! ## Using Mock Users
 //!
@@ -56,53 +136,551 @@
 //! print_current_username(&mut actual_users);
 //! ```
 
-use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
 
 pub use libc::{uid_t, gid_t};
 pub use base::{User, Group};
 pub use traits::{Users, Groups};
+use base::os::unix::{UserExt, GroupExt};
 
 
 /// A mocking users table that you can add your own users and groups to.
+#[derive(Debug)]
 pub struct MockUsers {
-    users: HashMap<uid_t, Arc<User>>,
-    groups: HashMap<gid_t, Arc<Group>>,
+
+    /// Every user and group in the table, in the order they were added,
+    /// which for ones read from a database is the order of its lines, so
+    /// names shared by more than one of them resolve to the first.
+    users: Vec<Arc<User>>,
+    groups: Vec<Arc<Group>>,
+
+    /// The position of the user or group that each ID belongs to. Others
+    /// read from a database with an ID that was already taken can only be
+    /// found by name or by listing.
+    uids: HashMap<uid_t, usize>,
+    gids: HashMap<gid_t, usize>,
+
     uid: uid_t,
+    euid: uid_t,
+    gid: gid_t,
+    egid: gid_t,
+    failures: HashMap<Lookup, Failure>,
+    delays: HashMap<Lookup, Duration>,
+}
+
+/// A failure that has been injected into a `MockUsers` table.
+#[derive(Debug)]
+struct Failure {
+    kind: io::ErrorKind,
+
+    /// How many more times the lookup should fail, or `None` to keep failing.
+    remaining: Mutex<Option<usize>>,
 }
 
 
 impl MockUsers {
 
-    /// Create a new, empty mock users table.
+    /// Starts building a mock users table with a
+    /// [`MockUsersBuilder`](struct.MockUsersBuilder.html).
+    pub fn builder() -> MockUsersBuilder {
+        MockUsersBuilder::default()
+    }
+
+    /// Create a new, empty mock users table. The effective user ID, and the
+    /// current and effective group IDs, all start out as the given ID too.
     pub fn with_current_uid(current_uid: uid_t) -> Self {
         Self {
-            users: HashMap::new(),
-            groups: HashMap::new(),
+            users: Vec::new(),
+            groups: Vec::new(),
+            uids: HashMap::new(),
+            gids: HashMap::new(),
             uid: current_uid,
+            euid: current_uid,
+            gid: current_uid,
+            egid: current_uid,
+            failures: HashMap::new(),
+            delays: HashMap::new(),
+        }
+    }
+
+    /// Sets the effective user ID, which otherwise starts out as the current
+    /// user ID.
+    pub fn with_effective_uid(mut self, effective_uid: uid_t) -> Self {
+        self.euid = effective_uid;
+        self
+    }
+
+    /// Sets the current group ID, which otherwise starts out as the current
+    /// user ID.
+    pub fn with_current_gid(mut self, current_gid: gid_t) -> Self {
+        self.gid = current_gid;
+        self
+    }
+
+    /// Sets the effective group ID, which otherwise starts out as the current
+    /// user ID.
+    pub fn with_effective_gid(mut self, effective_gid: gid_t) -> Self {
+        self.egid = effective_gid;
+        self
+    }
+
+    /// Creates a new mock users table containing the users and groups in the
+    /// given text, which is in the format of the `/etc/passwd` and
+    /// `/etc/group` files. The current and effective IDs all start out as 0.
+    ///
+    /// Blank lines, comments, and NIS `+` and `-` lines are ignored. If more
+    /// than one user or group has the same ID, looking up that ID finds only
+    /// the first one, the same as on a real system. The others can still be
+    /// found by name, and get listed along with the rest. Likewise, if more
+    /// than one has the same name, looking up that name finds the first.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` with the kind `InvalidData` if a line
+    /// has the wrong number of fields, or an ID that isn’t a number. The
+    /// message says which line it was.
+    pub fn from_passwd_and_group(passwd: &str, group: &str) -> io::Result<Self> {
+        let mut users = Self::with_current_uid(0);
+        users.add_passwd_entries(passwd)?;
+        users.add_group_entries(group)?;
+        Ok(users)
+    }
+
+    /// Creates a new mock users table by reading the files at the given
+    /// paths, which are in the format of the `/etc/passwd` and `/etc/group`
+    /// files. This works the same way as
+    /// [`from_passwd_and_group`](#method.from_passwd_and_group).
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` when an I/O error occurs while reading
+    /// either file, or if either of them contains an invalid line.
+    pub fn from_passwd_and_group_files<P, G>(passwd_path: P, group_path: G) -> io::Result<Self>
+    where P: AsRef<Path>,
+          G: AsRef<Path>,
+    {
+        let passwd = fs::read_to_string(passwd_path)?;
+        let group = fs::read_to_string(group_path)?;
+        Self::from_passwd_and_group(&passwd, &group)
+    }
+
+    /// Adds the users in the given text, which is in the format of the
+    /// `/etc/passwd` file, to the table.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` with the kind `InvalidData` if a line
+    /// is invalid, in which case none of the users get added.
+    pub fn add_passwd_entries(&mut self, passwd: &str) -> io::Result<()> {
+        let mut users = Vec::new();
+
+        for (number, fields) in database_lines(passwd) {
+            if fields.len() != 7 {
+                return Err(invalid_line("passwd", number, "expected 7 fields"));
+            }
+
+            let uid = parse_id(fields[2]).ok_or_else(|| invalid_line("passwd", number, "invalid user ID"))?;
+            let gid = parse_id(fields[3]).ok_or_else(|| invalid_line("passwd", number, "invalid group ID"))?;
+
+            users.push(User::new(uid, fields[0], gid)
+                .with_password(fields[1])
+                .with_home_dir(fields[5])
+                .with_shell(fields[6]));
         }
+
+        for user in users {
+            if self.uids.contains_key(&user.uid()) {
+                self.users.push(Arc::new(user));
+            }
+            else {
+                self.add_user(user);
+            }
+        }
+
+        Ok(())
     }
 
-    /// Add a user to the users table.
+    /// Adds the groups in the given text, which is in the format of the
+    /// `/etc/group` file, to the table.
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` with the kind `InvalidData` if a line
+    /// is invalid, in which case none of the groups get added.
+    pub fn add_group_entries(&mut self, group: &str) -> io::Result<()> {
+        let mut groups = Vec::new();
+
+        for (number, fields) in database_lines(group) {
+            if fields.len() != 4 {
+                return Err(invalid_line("group", number, "expected 4 fields"));
+            }
+
+            let gid = parse_id(fields[2]).ok_or_else(|| invalid_line("group", number, "invalid group ID"))?;
+
+            let members = fields[3].split(',').filter(|m| !m.is_empty());
+            groups.push(members.fold(Group::new(gid, fields[0]), |g, m| g.add_member(m)));
+        }
+
+        for group in groups {
+            if self.gids.contains_key(&group.gid()) {
+                self.groups.push(Arc::new(group));
+            }
+            else {
+                self.add_group(group);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add a user to the users table, replacing the user with the same ID,
+    /// if there is one, and returning it.
     pub fn add_user(&mut self, user: User) -> Option<Arc<User>> {
-        self.users.insert(user.uid(), Arc::new(user))
+        let user = Arc::new(user);
+        match self.uids.get(&user.uid()) {
+            Some(&index) => Some(mem::replace(&mut self.users[index], user)),
+            None => {
+                self.uids.insert(user.uid(), self.users.len());
+                self.users.push(user);
+                None
+            }
+        }
     }
 
-    /// Add a group to the groups table.
+    /// Add a group to the groups table, replacing the group with the same ID,
+    /// if there is one, and returning it.
     pub fn add_group(&mut self, group: Group) -> Option<Arc<Group>> {
-        self.groups.insert(group.gid(), Arc::new(group))
+        let group = Arc::new(group);
+        match self.gids.get(&group.gid()) {
+            Some(&index) => Some(mem::replace(&mut self.groups[index], group)),
+            None => {
+                self.gids.insert(group.gid(), self.groups.len());
+                self.groups.push(group);
+                None
+            }
+        }
+    }
+
+    /// Returns the user that the given ID belongs to.
+    fn user(&self, uid: uid_t) -> Option<&Arc<User>> {
+        self.uids.get(&uid).map(|&index| &self.users[index])
+    }
+
+    /// Returns the group that the given ID belongs to.
+    fn group(&self, gid: gid_t) -> Option<&Arc<Group>> {
+        self.gids.get(&gid).map(|&index| &self.groups[index])
+    }
+
+    /// Makes the given lookup fail with an error of the given kind, every
+    /// time it gets made, until [`clear_failures`](#method.clear_failures)
+    /// is called.
+    ///
+    /// Only lookups of users and groups by ID or name can fail. The
+    /// `try_get_*` methods return the error, and the other methods return
+    /// `None`, as if the user or group didn’t exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io;
+    /// use users::{Users, User};
+    /// use users::mock::{MockUsers, Lookup};
+    ///
+    /// let mut users = MockUsers::with_current_uid(1000);
+    /// users.add_user(User::new(1000, "fred", 1000));
+    /// users.fail_lookup(Lookup::UserByUid(1000), io::ErrorKind::TimedOut);
+    ///
+    /// let error = users.try_get_user_by_uid(1000).unwrap_err();
+    /// assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    /// assert!(users.get_user_by_uid(1000).is_none());
+    /// ```
+    pub fn fail_lookup(&mut self, lookup: Lookup, kind: io::ErrorKind) {
+        self.failures.insert(lookup, Failure { kind, remaining: Mutex::new(None) });
+    }
+
+    /// Makes the given lookup fail with an error of the given kind the next
+    /// given number of times it gets made, then succeed afterwards, like a
+    /// flaky directory service would.
+    pub fn fail_lookup_times(&mut self, lookup: Lookup, kind: io::ErrorKind, times: usize) {
+        self.failures.insert(lookup, Failure { kind, remaining: Mutex::new(Some(times)) });
+    }
+
+    /// Makes the given lookup sleep for the given amount of time before
+    /// returning, like a slow directory service would. A lookup that has also
+    /// been made to fail sleeps before failing.
+    pub fn delay_lookup(&mut self, lookup: Lookup, delay: Duration) {
+        self.delays.insert(lookup, delay);
+    }
+
+    /// Removes every failure and delay that has been added to the table.
+    pub fn clear_failures(&mut self) {
+        self.failures.clear();
+        self.delays.clear();
+    }
+
+    /// Sleeps, or returns an error, if the given lookup has been set up to.
+    fn inject(&self, lookup: Lookup) -> io::Result<()> {
+        if let Some(delay) = self.delays.get(&lookup) {
+            thread::sleep(*delay);
+        }
+
+        if let Some(failure) = self.failures.get(&lookup) {
+            let mut remaining = failure.remaining.lock().unwrap_or_else(|e| e.into_inner());
+            let fails = match *remaining {
+                Some(0)         => false,
+                Some(ref mut n) => { *n -= 1; true }
+                None            => true,
+            };
+
+            if fails {
+                let message = format!("Injected failure looking up {:?}", lookup);
+                return Err(io::Error::new(failure.kind, message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+/// A builder for a `MockUsers` table, with methods that describe users and
+/// the groups they belong to.
+///
+/// Calling [`user`](#method.user) adds a user, and the methods after it up
+/// to the next `user` call — `in_groups`, `primary_group`, `home`, and
+/// `shell` — describe that user. Groups don’t have to be declared before
+/// users are put in them:
+///
+/// - Each user’s primary group is a group with the same name as the user,
+///   unless another one is given with `primary_group`.
+/// - Any group that gets mentioned, but not declared with
+///   [`group`](#method.group), gets created. A user’s own group gets the same
+///   ID as the user, if it’s free, and every other group gets the first free
+///   ID from 1000 upwards.
+/// - Each group lists the users put in it with `in_groups` as its members,
+///   the same as the `/etc/group` file would. Users whose primary group it is
+///   don’t get listed.
+///
+/// The current and effective IDs are those of the user given to
+/// [`current`](#method.current) and its primary group, or 0 if there isn’t
+/// one.
+#[derive(Debug, Default)]
+pub struct MockUsersBuilder {
+    users: Vec<UserSpec>,
+    groups: Vec<(OsString, gid_t)>,
+    current: Option<OsString>,
+    effective: Option<OsString>,
+}
+
+/// A user that has been described to a `MockUsersBuilder`.
+#[derive(Debug)]
+struct UserSpec {
+    name: OsString,
+    uid: uid_t,
+    primary_group: Option<OsString>,
+    groups: Vec<OsString>,
+    home_dir: Option<PathBuf>,
+    shell: Option<PathBuf>,
+}
+
+impl MockUsersBuilder {
+
+    /// Adds a user with the given name and ID.
+    pub fn user<S: AsRef<OsStr> + ?Sized>(mut self, name: &S, uid: uid_t) -> Self {
+        self.users.push(UserSpec {
+            name: name.into(),
+            uid,
+            primary_group: None,
+            groups: Vec::new(),
+            home_dir: None,
+            shell: None,
+        });
+        self
     }
+
+    /// Puts the most recently added user in the groups with the given names.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if no user has been added yet.
+    pub fn in_groups<I>(mut self, group_names: I) -> Self
+    where I: IntoIterator,
+          I::Item: AsRef<OsStr>,
+    {
+        let user = self.last_user("in_groups");
+        user.groups.extend(group_names.into_iter().map(|g| g.as_ref().to_os_string()));
+        self
+    }
+
+    /// Sets the primary group of the most recently added user.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if no user has been added yet.
+    pub fn primary_group<S: AsRef<OsStr> + ?Sized>(mut self, group_name: &S) -> Self {
+        self.last_user("primary_group").primary_group = Some(group_name.into());
+        self
+    }
+
+    /// Sets the home directory of the most recently added user.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if no user has been added yet.
+    pub fn home<S: AsRef<OsStr> + ?Sized>(mut self, home_dir: &S) -> Self {
+        self.last_user("home").home_dir = Some(home_dir.as_ref().into());
+        self
+    }
+
+    /// Sets the shell of the most recently added user.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if no user has been added yet.
+    pub fn shell<S: AsRef<OsStr> + ?Sized>(mut self, shell: &S) -> Self {
+        self.last_user("shell").shell = Some(shell.as_ref().into());
+        self
+    }
+
+    /// Adds a group with the given name and ID.
+    pub fn group<S: AsRef<OsStr> + ?Sized>(mut self, name: &S, gid: gid_t) -> Self {
+        self.groups.push((name.into(), gid));
+        self
+    }
+
+    /// Makes the user with the given name the current and effective user.
+    pub fn current<S: AsRef<OsStr> + ?Sized>(mut self, name: &S) -> Self {
+        self.current = Some(name.into());
+        self
+    }
+
+    /// Makes the user with the given name the effective user, leaving the
+    /// current user as it is.
+    pub fn effective<S: AsRef<OsStr> + ?Sized>(mut self, name: &S) -> Self {
+        self.effective = Some(name.into());
+        self
+    }
+
+    fn last_user(&mut self, method: &str) -> &mut UserSpec {
+        match self.users.last_mut() {
+            Some(user) => user,
+            None       => panic!("MockUsersBuilder::{} called before any users were added", method),
+        }
+    }
+
+    /// Creates the mock users table.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the user given to `current` or `effective`
+    /// hasn’t been added.
+    pub fn build(self) -> MockUsers {
+        let mut gids = self.groups.clone();
+        let mut used = gids.iter().map(|&(_, gid)| gid).collect::<HashSet<_>>();
+
+        for user in &self.users {
+            let primary = user.primary_group.as_ref().unwrap_or(&user.name);
+            if ! gids.iter().any(|(name, _)| name == primary) {
+                let preferred = if primary == &user.name { Some(user.uid) } else { None };
+                gids.push((primary.clone(), allocate_gid(&mut used, preferred)));
+            }
+        }
+
+        for group_name in self.users.iter().flat_map(|u| &u.groups) {
+            if ! gids.iter().any(|(name, _)| name == group_name) {
+                gids.push((group_name.clone(), allocate_gid(&mut used, None)));
+            }
+        }
+
+        let gid_of = |name: &OsStr| gids.iter().find(|&(n, _)| n == name).map(|&(_, gid)| gid);
+        let primary_gid = |user: &UserSpec| gid_of(user.primary_group.as_ref().unwrap_or(&user.name)).unwrap();
+
+        let ids = |name: &Option<OsString>, role: &str| match *name {
+            Some(ref name) => match self.users.iter().find(|u| &u.name == name) {
+                Some(user) => (user.uid, primary_gid(user)),
+                None       => panic!("MockUsersBuilder: {} user {:?} was never added", role, name),
+            },
+            None => (0, 0),
+        };
+
+        let (uid, gid) = ids(&self.current, "current");
+        let (euid, egid) = match self.effective {
+            Some(_) => ids(&self.effective, "effective"),
+            None    => (uid, gid),
+        };
+
+        let mut mock = MockUsers::with_current_uid(uid)
+            .with_effective_uid(euid)
+            .with_current_gid(gid)
+            .with_effective_gid(egid);
+
+        for &(ref name, gid) in &gids {
+            let members = self.users.iter().filter(|u| u.groups.contains(name));
+            mock.add_group(members.fold(Group::new(gid, name), |g, u| g.add_member(&u.name)));
+        }
+
+        for spec in &self.users {
+            let mut user = User::new(spec.uid, &spec.name, primary_gid(spec));
+            if let Some(ref home_dir) = spec.home_dir {
+                user = user.with_home_dir(home_dir);
+            }
+            if let Some(ref shell) = spec.shell {
+                user = user.with_shell(shell);
+            }
+
+            mock.add_user(user);
+        }
+
+        mock
+    }
+}
+
+/// Picks an ID for a group that the builder is creating: the preferred one
+/// if it’s free, or else the first free one from 1000 upwards.
+fn allocate_gid(used: &mut HashSet<gid_t>, preferred: Option<gid_t>) -> gid_t {
+    let gid = match preferred {
+        Some(gid) if ! used.contains(&gid) => gid,
+        _ => (1000 ..).find(|gid| ! used.contains(gid)).unwrap(),
+    };
+
+    used.insert(gid);
+    gid
+}
+
+
+/// Splits the text of a users or groups database into its entries, along
+/// with their line numbers, skipping the lines that aren’t entries.
+fn database_lines(text: &str) -> impl Iterator<Item=(usize, Vec<&str>)> {
+    text.lines()
+        .enumerate()
+        .filter(|&(_, line)| !(line.trim().is_empty() || line.starts_with('#') || line.starts_with('+') || line.starts_with('-')))
+        .map(|(index, line)| (index + 1, line.split(':').collect()))
+}
+
+fn parse_id(field: &str) -> Option<u32> {
+    field.trim().parse().ok()
+}
+
+fn invalid_line(file: &str, number: usize, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Invalid {} line {}: {}", file, number, reason))
 }
 
 
 impl Users for MockUsers {
     fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
-        self.users.get(&uid).cloned()
+        self.try_get_user_by_uid(uid).unwrap_or_default()
     }
 
     fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
-        self.users.values().find(|u| u.name() == username.as_ref()).cloned()
+        self.try_get_user_by_name(username).unwrap_or_default()
     }
 
     fn get_current_uid(&self) -> uid_t {
@@ -110,42 +688,418 @@ impl Users for MockUsers {
     }
 
     fn get_current_username(&self) -> Option<Arc<OsStr>> {
-        self.users.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.user(self.uid).map(|u| Arc::clone(&u.name_arc))
     }
 
     fn get_effective_uid(&self) -> uid_t {
-        self.uid
+        self.euid
     }
 
     fn get_effective_username(&self) -> Option<Arc<OsStr>> {
-        self.users.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.user(self.euid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let username = username.as_ref();
+        let mut groups = self.groups.iter()
+                             .filter(|g| g.gid() == gid || g.members().iter().any(|m| m == username))
+                             .cloned()
+                             .collect::<Vec<_>>();
+
+        groups.sort_by_key(|g| g.gid());
+        Some(groups)
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let mut users = self.users.clone();
+        users.sort_by_key(|u| u.uid());
+        Box::new(users.into_iter())
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        self.inject(Lookup::UserByUid(uid))?;
+        Ok(self.user(uid).cloned())
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        self.inject(Lookup::UserByName(username.into()))?;
+        Ok(self.users.iter().find(|u| u.name() == username.as_ref()).cloned())
     }
 }
 
 
 impl Groups for MockUsers {
     fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
-        self.groups.get(&gid).cloned()
+        self.try_get_group_by_gid(gid).unwrap_or_default()
     }
 
     fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
-        self.groups.values().find(|g| g.name() == group_name.as_ref()).cloned()
+        self.try_get_group_by_name(group_name).unwrap_or_default()
     }
 
-    fn get_current_gid(&self) -> uid_t {
-        self.uid
+    fn get_current_gid(&self) -> gid_t {
+        self.gid
     }
 
     fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
-        self.groups.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.group(self.gid).map(|g| Arc::clone(&g.name_arc))
     }
 
-    fn get_effective_gid(&self) -> uid_t {
-        self.uid
+    fn get_effective_gid(&self) -> gid_t {
+        self.egid
     }
 
     fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
-        self.groups.get(&self.uid).map(|u| Arc::clone(&u.name_arc))
+        self.group(self.egid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let group = self.group(gid)?;
+        let mut members = self.users.iter()
+                              .filter(|u| u.primary_group_id() == gid || group.members().iter().any(|m| m == u.name()))
+                              .cloned()
+                              .collect::<Vec<_>>();
+
+        members.sort_by_key(|u| u.uid());
+        Some(members)
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let mut groups = self.groups.clone();
+        groups.sort_by_key(|g| g.gid());
+        Box::new(groups.into_iter())
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        self.inject(Lookup::GroupByGid(gid))?;
+        Ok(self.group(gid).cloned())
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        self.inject(Lookup::GroupByName(group_name.into()))?;
+        Ok(self.groups.iter().find(|g| g.name() == group_name.as_ref()).cloned())
+    }
+}
+
+
+/// A wrapper around a table of users and groups, such as a `MockUsers`, that
+/// records every lookup made through it, to check which ones reached the
+/// table it wraps.
+///
+/// Lookups get passed on to the wrapped table unchanged. The record can be
+/// read back with [`lookups`](#method.lookups), or checked with the
+/// `assert_looked_up_*` methods, which panic with the full record if the
+/// lookup wasn’t made the expected number of times.
+#[derive(Debug)]
+pub struct RecordingUsers<P> {
+    inner: P,
+    calls: Mutex<Vec<Call>>,
+}
+
+/// One call made through a `RecordingUsers`.
+#[derive(Debug, Clone)]
+pub struct Call {
+
+    /// Which method was called, and with what argument.
+    pub lookup: Lookup,
+
+    /// What the wrapped table returned.
+    pub result: Answer,
+}
+
+impl Call {
+
+    /// Returns whether the wrapped table returned a user, group, name, or
+    /// list. This is always `true` for the methods that return an ID, and
+    /// `false` for lookups that failed.
+    pub fn found(&self) -> bool {
+        match self.result {
+            Answer::User(ref user)        => user.is_some(),
+            Answer::Group(ref group)      => group.is_some(),
+            Answer::Name(ref name)        => name.is_some(),
+            Answer::Uid(_)                => true,
+            Answer::Gid(_)                => true,
+            Answer::UserList(ref users)   => users.is_some(),
+            Answer::GroupList(ref groups) => groups.is_some(),
+            Answer::Error(_)              => false,
+        }
+    }
+}
+
+/// The value returned from one call made through a `RecordingUsers`.
+///
+/// Listing every user or group records the whole list.
+#[derive(Debug, Clone)]
+#[allow(missing_docs)]
+pub enum Answer {
+    User(Option<Arc<User>>),
+    Group(Option<Arc<Group>>),
+    Name(Option<Arc<OsStr>>),
+    Uid(uid_t),
+    Gid(gid_t),
+    UserList(Option<Vec<Arc<User>>>),
+    GroupList(Option<Vec<Arc<Group>>>),
+
+    /// The lookup failed with an error of this kind.
+    Error(io::ErrorKind),
+}
+
+/// A value returned from the wrapped table that can be recorded.
+trait Recordable {
+    fn answer(&self) -> Answer;
+}
+
+impl Recordable for Option<Arc<User>> {
+    fn answer(&self) -> Answer { Answer::User(self.clone()) }
+}
+
+impl Recordable for Option<Arc<Group>> {
+    fn answer(&self) -> Answer { Answer::Group(self.clone()) }
+}
+
+impl Recordable for Option<Arc<OsStr>> {
+    fn answer(&self) -> Answer { Answer::Name(self.clone()) }
+}
+
+impl Recordable for Option<Vec<Arc<User>>> {
+    fn answer(&self) -> Answer { Answer::UserList(self.clone()) }
+}
+
+impl Recordable for Option<Vec<Arc<Group>>> {
+    fn answer(&self) -> Answer { Answer::GroupList(self.clone()) }
+}
+
+/// A method of the `Users` or `Groups` traits, along with its argument.
+///
+/// The `try_get_*` methods count as the same lookups as the methods without
+/// `try_`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[allow(missing_docs)]
+pub enum Lookup {
+    UserByUid(uid_t),
+    UserByName(OsString),
+    UserNameByUid(uid_t),
+    CurrentUid,
+    CurrentUsername,
+    EffectiveUid,
+    EffectiveUsername,
+    UserGroups(OsString, gid_t),
+    AllUsers,
+
+    GroupByGid(gid_t),
+    GroupByName(OsString),
+    GroupNameByGid(gid_t),
+    CurrentGid,
+    CurrentGroupname,
+    EffectiveGid,
+    EffectiveGroupname,
+    GroupMembers(gid_t),
+    AllGroups,
+}
+
+impl<P> RecordingUsers<P> {
+
+    /// Wraps the given table, with nothing recorded yet.
+    pub fn new(inner: P) -> Self {
+        Self { inner, calls: Mutex::new(Vec::new()) }
+    }
+
+    /// Returns the wrapped table. Lookups made on it directly don’t get
+    /// recorded.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Returns the wrapped table, discarding the record.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    /// Returns every call made so far, oldest first.
+    pub fn lookups(&self) -> Vec<Call> {
+        self.calls().clone()
+    }
+
+    /// Returns the number of times the given lookup has been made.
+    pub fn times_looked_up(&self, lookup: &Lookup) -> usize {
+        self.calls().iter().filter(|c| &c.lookup == lookup).count()
+    }
+
+    /// Forgets every call made so far.
+    pub fn clear(&self) {
+        self.calls().clear();
+    }
+
+    /// Checks that the user with the given ID has been looked up the given
+    /// number of times, with `get_user_by_uid`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the number of times is different.
+    pub fn assert_looked_up_uid(&self, uid: uid_t, times: usize) {
+        self.assert_times(Lookup::UserByUid(uid), times);
+    }
+
+    /// Checks that the user with the given name has been looked up the given
+    /// number of times, with `get_user_by_name`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the number of times is different.
+    pub fn assert_looked_up_username<S: AsRef<OsStr> + ?Sized>(&self, username: &S, times: usize) {
+        self.assert_times(Lookup::UserByName(username.into()), times);
+    }
+
+    /// Checks that the group with the given ID has been looked up the given
+    /// number of times, with `get_group_by_gid`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the number of times is different.
+    pub fn assert_looked_up_gid(&self, gid: gid_t, times: usize) {
+        self.assert_times(Lookup::GroupByGid(gid), times);
+    }
+
+    /// Checks that the group with the given name has been looked up the given
+    /// number of times, with `get_group_by_name`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if the number of times is different.
+    pub fn assert_looked_up_group_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S, times: usize) {
+        self.assert_times(Lookup::GroupByName(group_name.into()), times);
+    }
+
+    fn assert_times(&self, lookup: Lookup, times: usize) {
+        let calls = self.calls();
+        let actual = calls.iter().filter(|c| c.lookup == lookup).count();
+        assert!(actual == times, "Expected {:?} to be looked up {} times, but it was looked up {} times. Lookups: {:#?}",
+                lookup, times, actual, *calls);
+    }
+
+    fn record<T: Recordable>(&self, lookup: Lookup, result: T) -> T {
+        self.calls().push(Call { lookup, result: result.answer() });
+        result
+    }
+
+    fn try_record<T: Recordable>(&self, lookup: Lookup, result: io::Result<T>) -> io::Result<T> {
+        let answer = match result {
+            Ok(ref value) => value.answer(),
+            Err(ref e)    => Answer::Error(e.kind()),
+        };
+
+        self.calls().push(Call { lookup, result: answer });
+        result
+    }
+
+    fn record_uid(&self, lookup: Lookup, uid: uid_t) -> uid_t {
+        self.calls().push(Call { lookup, result: Answer::Uid(uid) });
+        uid
+    }
+
+    fn record_gid(&self, lookup: Lookup, gid: gid_t) -> gid_t {
+        self.calls().push(Call { lookup, result: Answer::Gid(gid) });
+        gid
+    }
+
+    fn calls(&self) -> MutexGuard<'_, Vec<Call>> {
+        self.calls.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<P: Users> Users for RecordingUsers<P> {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.record(Lookup::UserByUid(uid), self.inner.get_user_by_uid(uid))
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.record(Lookup::UserByName(username.into()), self.inner.get_user_by_name(username))
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.record_uid(Lookup::CurrentUid, self.inner.get_current_uid())
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.record(Lookup::CurrentUsername, self.inner.get_current_username())
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.record_uid(Lookup::EffectiveUid, self.inner.get_effective_uid())
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.record(Lookup::EffectiveUsername, self.inner.get_effective_username())
+    }
+
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        self.record(Lookup::UserNameByUid(uid), self.inner.get_user_name_by_uid(uid))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        self.record(Lookup::UserGroups(username.into(), gid), self.inner.get_user_groups(username, gid))
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let users = self.inner.all_users().collect::<Vec<_>>();
+        self.record(Lookup::AllUsers, Some(users.clone()));
+        Box::new(users.into_iter())
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        self.try_record(Lookup::UserByUid(uid), self.inner.try_get_user_by_uid(uid))
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        self.try_record(Lookup::UserByName(username.into()), self.inner.try_get_user_by_name(username))
+    }
+}
+
+impl<P: Groups> Groups for RecordingUsers<P> {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.record(Lookup::GroupByGid(gid), self.inner.get_group_by_gid(gid))
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.record(Lookup::GroupByName(group_name.into()), self.inner.get_group_by_name(group_name))
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.record_gid(Lookup::CurrentGid, self.inner.get_current_gid())
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.record(Lookup::CurrentGroupname, self.inner.get_current_groupname())
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.record_gid(Lookup::EffectiveGid, self.inner.get_effective_gid())
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.record(Lookup::EffectiveGroupname, self.inner.get_effective_groupname())
+    }
+
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        self.record(Lookup::GroupNameByGid(gid), self.inner.get_group_name_by_gid(gid))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        self.record(Lookup::GroupMembers(gid), self.inner.get_group_members(gid))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let groups = self.inner.all_groups().collect::<Vec<_>>();
+        self.record(Lookup::AllGroups, Some(groups.clone()));
+        Box::new(groups.into_iter())
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        self.try_record(Lookup::GroupByGid(gid), self.inner.try_get_group_by_gid(gid))
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        self.try_record(Lookup::GroupByName(group_name.into()), self.inner.try_get_group_by_name(group_name))
     }
 }
 
@@ -156,7 +1110,8 @@ mod test {
     use base::{User, Group};
     use traits::{Users, Groups};
 
-    use std::ffi::OsStr;
+    use std::ffi::{OsStr, OsString};
+    use std::io;
     use std::sync::Arc;
 
     #[test]
@@ -234,4 +1189,332 @@ mod test {
         assert_eq!(None,
                    users.get_group_by_gid(1337).map(|g| Arc::clone(&g.name_arc)))
     }
+
+    #[test]
+    fn ids_default_to_current_uid() {
+        let users = MockUsers::with_current_uid(1337);
+        assert_eq!(1337, users.get_effective_uid());
+        assert_eq!(1337, users.get_current_gid());
+        assert_eq!(1337, users.get_effective_gid());
+    }
+
+    #[test]
+    fn effective_username() {
+        let mut users = MockUsers::with_current_uid(1337).with_effective_uid(0);
+        users.add_user(User::new(1337, "fred", 101));
+        users.add_user(User::new(0, "root", 0));
+        assert_eq!(Some(Arc::from(OsStr::new("fred"))), users.get_current_username());
+        assert_eq!(Some(Arc::from(OsStr::new("root"))), users.get_effective_username());
+    }
+
+    #[test]
+    fn groupnames() {
+        let mut users = MockUsers::with_current_uid(1337).with_current_gid(101).with_effective_gid(0);
+        users.add_group(Group::new(101, "freds"));
+        users.add_group(Group::new(0, "wheel"));
+        assert_eq!(101, users.get_current_gid());
+        assert_eq!(Some(Arc::from(OsStr::new("freds"))), users.get_current_groupname());
+        assert_eq!(Some(Arc::from(OsStr::new("wheel"))), users.get_effective_groupname());
+    }
+
+    const PASSWD: &str = "\
+        # A comment\n\
+        root:x:0:0:root:/root:/bin/bash\n\
+        \n\
+        fred:$6$salt$hash:1000:100:Fred,,,:/home/fred:/bin/zsh\n\
+        toor:x:0:0::/root:/bin/sh\n\
+        +@netgroup::::::\n";
+
+    const GROUP: &str = "\
+        root:x:0:\n\
+        users:x:100:fred,root\n\
+        wheel:x:0:toor\n";
+
+    #[test]
+    fn parsed() {
+        use base::os::unix::{UserExt, GroupExt};
+        use std::path::Path;
+
+        let users = MockUsers::from_passwd_and_group(PASSWD, GROUP).unwrap();
+        let fred = users.get_user_by_name("fred").unwrap();
+        assert_eq!(fred.uid(), 1000);
+        assert_eq!(fred.primary_group_id(), 100);
+        assert_eq!(fred.password(), OsStr::new("$6$salt$hash"));
+        assert_eq!(fred.home_dir(), Path::new("/home/fred"));
+        assert_eq!(fred.shell(), Path::new("/bin/zsh"));
+
+        let group = users.get_group_by_gid(100).unwrap();
+        assert_eq!(group.members(), &[ OsString::from("fred"), OsString::from("root") ]);
+        assert!(users.get_group_by_gid(0).unwrap().members().is_empty());
+    }
+
+    #[test]
+    fn parsed_duplicate_id() {
+        let users = MockUsers::from_passwd_and_group(PASSWD, GROUP).unwrap();
+        assert_eq!(users.get_user_by_uid(0).unwrap().name(), OsStr::new("root"));
+        assert_eq!(users.get_user_by_name("toor").unwrap().uid(), 0);
+        assert_eq!(users.all_users().map(|u| u.uid()).collect::<Vec<_>>(), vec![ 0, 0, 1000 ]);
+
+        assert_eq!(users.get_group_by_gid(0).unwrap().name(), OsStr::new("root"));
+        assert_eq!(users.get_group_by_name("wheel").unwrap().gid(), 0);
+        assert_eq!(users.get_user_groups("toor", 0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parsed_duplicate_name() {
+        let passwd = "fred:x:1001:100::/home/fred:/bin/sh\nfred:x:1000:100::/home/fred:/bin/sh\n";
+        let group = "staff:x:101:\nstaff:x:100:\n";
+
+        // Each table hashes differently, so an order that came from a hash
+        // map would change from one to the next.
+        for _ in 0 .. 16 {
+            let users = MockUsers::from_passwd_and_group(passwd, group).unwrap();
+            assert_eq!(users.get_user_by_name("fred").unwrap().uid(), 1001);
+            assert_eq!(users.get_group_by_name("staff").unwrap().gid(), 101);
+            assert_eq!(users.all_users().map(|u| u.uid()).collect::<Vec<_>>(), vec![ 1000, 1001 ]);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = MockUsers::from_passwd_and_group("root:x:0:0:root:/root:/bin/bash\nfred:x:1000\n", "").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Invalid passwd line 2: expected 7 fields");
+
+        let error = MockUsers::from_passwd_and_group("", "users:x:one-hundred:\n").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid group line 1: invalid group ID");
+    }
+
+    #[test]
+    fn builder() {
+        use base::os::unix::{UserExt, GroupExt};
+        use std::path::Path;
+
+        let users = MockUsers::builder()
+            .user("alice", 1000).in_groups(&["wheel", "docker"]).home("/home/alice")
+            .user("bob", 1001).in_groups(&["docker"]).primary_group("staff").shell("/bin/zsh")
+            .group("wheel", 10)
+            .current("alice")
+            .build();
+
+        let alice = users.get_user_by_name("alice").unwrap();
+        assert_eq!(alice.primary_group_id(), 1000);
+        assert_eq!(alice.home_dir(), Path::new("/home/alice"));
+        assert_eq!(users.get_group_by_gid(1000).unwrap().name(), OsStr::new("alice"));
+
+        let bob = users.get_user_by_name("bob").unwrap();
+        assert_eq!(bob.shell(), Path::new("/bin/zsh"));
+        assert_eq!(users.get_group_by_gid(bob.primary_group_id()).unwrap().name(), OsStr::new("staff"));
+
+        assert_eq!(users.get_group_by_name("wheel").unwrap().members(), &[ OsString::from("alice") ]);
+        assert_eq!(users.get_group_by_name("docker").unwrap().members(), &[ OsString::from("alice"), OsString::from("bob") ]);
+        assert!(users.get_group_by_name("alice").unwrap().members().is_empty());
+
+        assert_eq!((users.get_current_uid(), users.get_current_gid()), (1000, 1000));
+        assert_eq!((users.get_effective_uid(), users.get_effective_gid()), (1000, 1000));
+    }
+
+    #[test]
+    fn builder_allocates_gids() {
+        let users = MockUsers::builder()
+            .group("taken", 1000)
+            .user("carol", 1000)
+            .user("dave", 1002).in_groups(&["new"])
+            .build();
+
+        assert_eq!(users.get_user_by_name("carol").unwrap().primary_group_id(), 1001);
+        assert_eq!(users.get_user_by_name("dave").unwrap().primary_group_id(), 1002);
+        assert_eq!(users.get_group_by_name("new").unwrap().gid(), 1003);
+    }
+
+    #[test]
+    fn builder_effective() {
+        let users = MockUsers::builder()
+            .user("root", 0)
+            .user("erin", 1000).primary_group("users").group("users", 100)
+            .current("erin")
+            .effective("root")
+            .build();
+
+        assert_eq!((users.get_current_uid(), users.get_current_gid()), (1000, 100));
+        assert_eq!((users.get_effective_uid(), users.get_effective_gid()), (0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "current user \"nobody\" was never added")]
+    fn builder_unknown_current() {
+        MockUsers::builder().current("nobody").build();
+    }
+
+    #[test]
+    fn recording() {
+        use super::{RecordingUsers, Answer, Lookup};
+
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_user(User::new(1000, "fred", 100));
+        users.add_group(Group::new(100, "staff"));
+
+        let recorder = RecordingUsers::new(users);
+        assert!(recorder.get_user_by_uid(1000).is_some());
+        assert!(recorder.get_user_by_uid(1000).is_some());
+        assert!(recorder.get_user_by_name("nobody").is_none());
+        assert_eq!(recorder.get_current_gid(), 1000);
+        assert!(recorder.get_group_name_by_gid(100).is_some());
+
+        recorder.assert_looked_up_uid(1000, 2);
+        recorder.assert_looked_up_uid(1001, 0);
+        recorder.assert_looked_up_username("nobody", 1);
+        recorder.assert_looked_up_gid(100, 0);
+        assert_eq!(recorder.times_looked_up(&Lookup::GroupNameByGid(100)), 1);
+
+        let lookups = recorder.lookups();
+        assert_eq!(lookups[0].lookup, Lookup::UserByUid(1000));
+        match lookups[0].result {
+            Answer::User(Some(ref user)) => assert_eq!(user.name(), OsStr::new("fred")),
+            ref other                    => panic!("Unexpected answer {:?}", other),
+        }
+
+        assert_eq!(lookups[2].lookup, Lookup::UserByName("nobody".into()));
+        assert!(!lookups[2].found());
+        assert_eq!(lookups[3].lookup, Lookup::CurrentGid);
+        assert!(lookups[3].found());
+
+        match lookups[3].result {
+            Answer::Gid(gid) => assert_eq!(gid, 1000),
+            ref other        => panic!("Unexpected answer {:?}", other),
+        }
+
+        recorder.clear();
+        assert!(recorder.lookups().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected UserByUid(1000) to be looked up 1 times, but it was looked up 0 times")]
+    fn recording_assertion_fails() {
+        use super::RecordingUsers;
+
+        let recorder = RecordingUsers::new(MockUsers::with_current_uid(1000));
+        recorder.get_user_by_uid(1001);
+        recorder.assert_looked_up_uid(1000, 1);
+    }
+
+    #[test]
+    fn injected_failures() {
+        use super::Lookup;
+
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_user(User::new(1000, "fred", 100));
+        users.add_group(Group::new(100, "staff"));
+        users.fail_lookup(Lookup::UserByName("fred".into()), io::ErrorKind::TimedOut);
+        users.fail_lookup(Lookup::GroupByGid(100), io::ErrorKind::Interrupted);
+
+        assert_eq!(users.try_get_user_by_name("fred").unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(users.get_user_by_name("fred").is_none());
+        assert!(users.try_get_user_by_uid(1000).unwrap().is_some());
+        assert!(users.try_get_user_by_uid(1001).unwrap().is_none());
+
+        assert_eq!(users.try_get_group_by_gid(100).unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert!(users.get_group_name_by_gid(100).is_none());
+        assert!(users.try_get_group_by_name("staff").unwrap().is_some());
+
+        users.clear_failures();
+        assert!(users.get_user_by_name("fred").is_some());
+        assert!(users.get_group_by_gid(100).is_some());
+    }
+
+    #[test]
+    fn injected_failures_run_out() {
+        use super::Lookup;
+
+        let mut users = MockUsers::with_current_uid(1000);
+        users.add_user(User::new(1000, "fred", 100));
+        users.fail_lookup_times(Lookup::UserByUid(1000), io::ErrorKind::TimedOut, 2);
+
+        assert!(users.try_get_user_by_uid(1000).is_err());
+        assert!(users.get_user_by_uid(1000).is_none());
+        assert!(users.try_get_user_by_uid(1000).unwrap().is_some());
+    }
+
+    #[test]
+    fn injected_delays() {
+        use super::Lookup;
+        use std::time::{Duration, Instant};
+
+        let mut users = MockUsers::with_current_uid(1000);
+        users.delay_lookup(Lookup::GroupByName("slow".into()), Duration::from_millis(50));
+
+        let started = Instant::now();
+        assert!(users.get_group_by_name("slow").is_none());
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn recording_failures() {
+        use super::{RecordingUsers, Answer, Lookup};
+
+        let mut users = MockUsers::with_current_uid(1000);
+        users.fail_lookup(Lookup::GroupByGid(100), io::ErrorKind::TimedOut);
+
+        let recorder = RecordingUsers::new(users);
+        assert!(recorder.try_get_group_by_gid(100).is_err());
+
+        let lookups = recorder.lookups();
+        assert_eq!(lookups.len(), 1);
+        assert_eq!(lookups[0].lookup, Lookup::GroupByGid(100));
+        assert!(!lookups[0].found());
+
+        match lookups[0].result {
+            Answer::Error(kind) => assert_eq!(kind, io::ErrorKind::TimedOut),
+            ref other           => panic!("Unexpected answer {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memberships() {
+        let users = MockUsers::builder()
+            .user("alice", 1000).in_groups(&["wheel", "docker"])
+            .user("bob", 1001).primary_group("wheel")
+            .group("wheel", 10)
+            .build();
+
+        let gids = |groups: Vec<Arc<Group>>| groups.iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids(users.get_user_groups("alice", 1000).unwrap()), vec![ 10, 1000, 1001 ]);
+        assert_eq!(gids(users.get_user_groups("bob", 10).unwrap()), vec![ 10 ]);
+
+        let uids = |members: Vec<Arc<User>>| members.iter().map(|u| u.uid()).collect::<Vec<_>>();
+        assert_eq!(uids(users.get_group_members(10).unwrap()), vec![ 1000, 1001 ]);
+        assert_eq!(uids(users.get_group_members(1000).unwrap()), vec![ 1000 ]);
+        assert!(users.get_group_members(999).is_none());
+    }
+
+    #[test]
+    fn listing() {
+        let users = MockUsers::builder()
+            .user("carol", 1002)
+            .user("alice", 1000).in_groups(&["wheel"])
+            .group("wheel", 10)
+            .build();
+
+        let uids = users.all_users().map(|u| u.uid()).collect::<Vec<_>>();
+        assert_eq!(uids, vec![ 1000, 1002 ]);
+
+        let gids = users.all_groups().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 10, 1000, 1002 ]);
+    }
+
+    #[test]
+    fn recording_listing() {
+        use super::{RecordingUsers, Answer, Lookup};
+
+        let recorder = RecordingUsers::new(MockUsers::builder().user("alice", 1000).build());
+        assert_eq!(recorder.all_users().count(), 1);
+
+        let lookups = recorder.lookups();
+        assert_eq!(lookups[0].lookup, Lookup::AllUsers);
+
+        match lookups[0].result {
+            Answer::UserList(Some(ref users)) => assert_eq!(users[0].name(), OsStr::new("alice")),
+            ref other                         => panic!("Unexpected answer {:?}", other),
+        }
+    }
 }
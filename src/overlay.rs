@@ -0,0 +1,477 @@
+//! Overriding a few users and groups on top of another producer.
+//!
+//! An [`Overlay`](struct.Overlay.html) wraps another producer of users and
+//! groups, such as a [`UsersCache`](../cache/struct.UsersCache.html), and
+//! lets users and groups be added, replaced, or hidden, without changing
+//! anything about the producer underneath. This is useful in tests that only
+//! need to fake one or two accounts, while everything else resolves the same
+//! way it normally would:
+//!
+//! ```
+//! use users::{Users, UsersCache, User};
+//! use users::overlay::Overlay;
+//!
+//! let mut overlay = Overlay::new(UsersCache::new()).with_current_uid(4321);
+//! overlay.add_user(User::new(4321, "tester", 4321));
+//!
+//! assert_eq!(&*overlay.get_current_username().unwrap(), "tester");
+//! assert_eq!(overlay.get_user_by_uid(0).unwrap().uid(), 0);
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io;
+use std::sync::Arc;
+
+use libc::{uid_t, gid_t};
+
+use base::{User, Group};
+use base::os::unix::GroupExt;
+use traits::{Users, Groups};
+
+
+/// A producer of users and groups that answers lookups from its own table
+/// first, then from the producer it wraps.
+///
+/// A user or group added to the overlay replaces any with the same ID
+/// underneath, even when being looked up by its old name. A hidden one
+/// can’t be looked up at all, as if it didn’t exist.
+///
+/// The current and effective user and group IDs are the ones underneath,
+/// unless they’ve been changed with the `with_*` methods.
+///
+/// A user’s groups, and a group’s members, come from the producer underneath,
+/// leaving out any that have been hidden, with the groups and users in the
+/// overlay added. Asking for the groups of a user underneath that has been
+/// hidden or replaced, by its name from underneath, returns `None`. A
+/// replaced group keeps the members underneath whose primary group it is,
+/// but otherwise only has the members it lists.
+#[derive(Debug)]
+pub struct Overlay<P> {
+    inner: P,
+
+    users: HashMap<uid_t, Arc<User>>,
+    groups: HashMap<gid_t, Arc<Group>>,
+    hidden_users: HashSet<uid_t>,
+    hidden_groups: HashSet<gid_t>,
+
+    uid: Option<uid_t>,
+    euid: Option<uid_t>,
+    gid: Option<gid_t>,
+    egid: Option<gid_t>,
+}
+
+impl<P> Overlay<P> {
+
+    /// Wraps the given producer, with nothing overridden yet.
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+
+            users: HashMap::new(),
+            groups: HashMap::new(),
+            hidden_users: HashSet::new(),
+            hidden_groups: HashSet::new(),
+
+            uid: None,
+            euid: None,
+            gid: None,
+            egid: None,
+        }
+    }
+
+    /// Sets the current user ID. The effective user ID stays as it is.
+    pub fn with_current_uid(mut self, current_uid: uid_t) -> Self {
+        self.uid = Some(current_uid);
+        self
+    }
+
+    /// Sets the effective user ID.
+    pub fn with_effective_uid(mut self, effective_uid: uid_t) -> Self {
+        self.euid = Some(effective_uid);
+        self
+    }
+
+    /// Sets the current group ID.
+    pub fn with_current_gid(mut self, current_gid: gid_t) -> Self {
+        self.gid = Some(current_gid);
+        self
+    }
+
+    /// Sets the effective group ID.
+    pub fn with_effective_gid(mut self, effective_gid: gid_t) -> Self {
+        self.egid = Some(effective_gid);
+        self
+    }
+
+    /// Adds a user to the overlay, replacing any user with the same ID,
+    /// and showing it again if it was hidden. Returns the user that was
+    /// previously in the overlay with that ID, if there was one.
+    pub fn add_user(&mut self, user: User) -> Option<Arc<User>> {
+        self.hidden_users.remove(&user.uid());
+        self.users.insert(user.uid(), Arc::new(user))
+    }
+
+    /// Adds a group to the overlay, replacing any group with the same ID,
+    /// and showing it again if it was hidden. Returns the group that was
+    /// previously in the overlay with that ID, if there was one.
+    pub fn add_group(&mut self, group: Group) -> Option<Arc<Group>> {
+        self.hidden_groups.remove(&group.gid());
+        self.groups.insert(group.gid(), Arc::new(group))
+    }
+
+    /// Hides the user with the given ID, removing it from the overlay if it
+    /// was added there.
+    pub fn hide_user(&mut self, uid: uid_t) {
+        self.users.remove(&uid);
+        self.hidden_users.insert(uid);
+    }
+
+    /// Hides the group with the given ID, removing it from the overlay if it
+    /// was added there.
+    pub fn hide_group(&mut self, gid: gid_t) {
+        self.groups.remove(&gid);
+        self.hidden_groups.insert(gid);
+    }
+
+    /// Removes every user and group that has been added or hidden, and
+    /// every ID that has been set, so lookups go straight through again.
+    pub fn clear(&mut self) {
+        self.users.clear();
+        self.groups.clear();
+        self.hidden_users.clear();
+        self.hidden_groups.clear();
+
+        self.uid = None;
+        self.euid = None;
+        self.gid = None;
+        self.egid = None;
+    }
+
+    /// Returns the producer underneath.
+    pub fn inner(&self) -> &P {
+        &self.inner
+    }
+
+    /// Returns the producer underneath, discarding the overlay.
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+
+    /// Whether a user with the given ID from underneath should be returned.
+    fn passes_user(&self, uid: uid_t) -> bool {
+        !self.hidden_users.contains(&uid) && !self.users.contains_key(&uid)
+    }
+
+    /// Whether a group with the given ID from underneath should be returned.
+    fn passes_group(&self, gid: gid_t) -> bool {
+        !self.hidden_groups.contains(&gid) && !self.groups.contains_key(&gid)
+    }
+
+    fn overlay_user(&self, uid: uid_t) -> Option<Option<Arc<User>>> {
+        if let Some(user) = self.users.get(&uid) {
+            Some(Some(Arc::clone(user)))
+        }
+        else if self.hidden_users.contains(&uid) {
+            Some(None)
+        }
+        else {
+            None
+        }
+    }
+
+    fn overlay_group(&self, gid: gid_t) -> Option<Option<Arc<Group>>> {
+        if let Some(group) = self.groups.get(&gid) {
+            Some(Some(Arc::clone(group)))
+        }
+        else if self.hidden_groups.contains(&gid) {
+            Some(None)
+        }
+        else {
+            None
+        }
+    }
+}
+
+impl<P: Users + Groups> Users for Overlay<P> {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        match self.overlay_user(uid) {
+            Some(user) => user,
+            None       => self.inner.get_user_by_uid(uid),
+        }
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        let username = username.as_ref();
+        match self.users.values().find(|u| u.name() == username) {
+            Some(user) => Some(Arc::clone(user)),
+            None       => self.inner.get_user_by_name(username).filter(|u| self.passes_user(u.uid())),
+        }
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.uid.unwrap_or_else(|| self.inner.get_current_uid())
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_name_by_uid(self.get_current_uid())
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.euid.unwrap_or_else(|| self.inner.get_effective_uid())
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_name_by_uid(self.get_effective_uid())
+    }
+
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        match self.overlay_user(uid) {
+            Some(user) => user.map(|u| Arc::clone(&u.name_arc)),
+            None       => self.inner.get_user_name_by_uid(uid),
+        }
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        match self.overlay_user(uid) {
+            Some(user) => Ok(user),
+            None       => self.inner.try_get_user_by_uid(uid),
+        }
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        let username = username.as_ref();
+        match self.users.values().find(|u| u.name() == username) {
+            Some(user) => Ok(Some(Arc::clone(user))),
+            None       => Ok(self.inner.try_get_user_by_name(username)?.filter(|u| self.passes_user(u.uid()))),
+        }
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let username = username.as_ref();
+
+        // A user underneath that has been hidden or replaced has no groups
+        // of its own, as it can’t be looked up by that name.
+        if !self.users.values().any(|u| u.name() == username) {
+            if let Some(user) = self.inner.get_user_by_name(username) {
+                if !self.passes_user(user.uid()) {
+                    return None;
+                }
+            }
+        }
+
+        let mut groups = self.inner.get_user_groups(username, gid).unwrap_or_default();
+        groups.retain(|g| self.passes_group(g.gid()));
+        groups.extend(self.groups.values()
+                          .filter(|g| g.gid() == gid || g.members().iter().any(|m| m == username))
+                          .cloned());
+
+        groups.sort_by_key(|g| g.gid());
+        Some(groups)
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let mut users = self.inner.all_users().filter(|u| self.passes_user(u.uid())).collect::<Vec<_>>();
+        users.extend(self.users.values().cloned());
+        users.sort_by_key(|u| u.uid());
+        Box::new(users.into_iter())
+    }
+}
+
+impl<P: Users + Groups> Groups for Overlay<P> {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        match self.overlay_group(gid) {
+            Some(group) => group,
+            None        => self.inner.get_group_by_gid(gid),
+        }
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        let group_name = group_name.as_ref();
+        match self.groups.values().find(|g| g.name() == group_name) {
+            Some(group) => Some(Arc::clone(group)),
+            None        => self.inner.get_group_by_name(group_name).filter(|g| self.passes_group(g.gid())),
+        }
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.gid.unwrap_or_else(|| self.inner.get_current_gid())
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_name_by_gid(self.get_current_gid())
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.egid.unwrap_or_else(|| self.inner.get_effective_gid())
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_name_by_gid(self.get_effective_gid())
+    }
+
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        match self.overlay_group(gid) {
+            Some(group) => group.map(|g| Arc::clone(&g.name_arc)),
+            None        => self.inner.get_group_name_by_gid(gid),
+        }
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        match self.overlay_group(gid) {
+            Some(group) => Ok(group),
+            None        => self.inner.try_get_group_by_gid(gid),
+        }
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        let group_name = group_name.as_ref();
+        match self.groups.values().find(|g| g.name() == group_name) {
+            Some(group) => Ok(Some(Arc::clone(group))),
+            None        => Ok(self.inner.try_get_group_by_name(group_name)?.filter(|g| self.passes_group(g.gid()))),
+        }
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let group = self.get_group_by_gid(gid)?;
+        let replaced = self.groups.contains_key(&gid);
+
+        let mut members = self.inner.get_group_members(gid).unwrap_or_default();
+        members.retain(|u| self.passes_user(u.uid()) && (!replaced || u.primary_group_id() == gid));
+        members.extend(group.members().iter().filter_map(|name| self.get_user_by_name(name)));
+        members.extend(self.users.values().filter(|u| u.primary_group_id() == gid).cloned());
+
+        members.sort_by_key(|u| u.uid());
+        members.dedup_by_key(|u| u.uid());
+        Some(members)
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let mut groups = self.inner.all_groups().filter(|g| self.passes_group(g.gid())).collect::<Vec<_>>();
+        groups.extend(self.groups.values().cloned());
+        groups.sort_by_key(|g| g.gid());
+        Box::new(groups.into_iter())
+    }
+}
+
+
+#[cfg(all(test, feature = "mock"))]
+mod test {
+    use super::*;
+    use mock::MockUsers;
+
+    fn underneath() -> MockUsers {
+        let mut users = MockUsers::with_current_uid(1000).with_current_gid(100).with_effective_gid(100);
+        users.add_user(User::new(0, "root", 0));
+        users.add_user(User::new(1000, "fred", 100));
+        users.add_user(User::new(1001, "bob", 100));
+        users.add_group(Group::new(0, "root"));
+        users.add_group(Group::new(100, "staff").add_member("root"));
+        users
+    }
+
+    fn uids(users: Vec<Arc<User>>) -> Vec<uid_t> {
+        users.iter().map(|u| u.uid()).collect()
+    }
+
+    #[test]
+    fn passes_through() {
+        let overlay = Overlay::new(underneath());
+        assert_eq!(overlay.get_user_by_uid(1000).unwrap().name(), "fred");
+        assert_eq!(overlay.get_group_by_name("staff").unwrap().gid(), 100);
+        assert_eq!(&*overlay.get_current_username().unwrap(), "fred");
+        assert_eq!(overlay.get_effective_gid(), 100);
+    }
+
+    #[test]
+    fn added() {
+        let mut overlay = Overlay::new(underneath());
+        overlay.add_user(User::new(2000, "tester", 100));
+        overlay.add_group(Group::new(200, "testers").add_member("tester"));
+
+        assert_eq!(overlay.get_user_by_name("tester").unwrap().uid(), 2000);
+        assert_eq!(&*overlay.get_group_name_by_gid(200).unwrap(), "testers");
+        assert_eq!(uids(overlay.all_users().collect()), vec![ 0, 1000, 1001, 2000 ]);
+        assert_eq!(overlay.all_groups().count(), 3);
+    }
+
+    #[test]
+    fn replaced() {
+        let mut overlay = Overlay::new(underneath());
+        overlay.add_user(User::new(1000, "freddie", 100));
+
+        assert_eq!(overlay.get_user_by_uid(1000).unwrap().name(), "freddie");
+        assert!(overlay.get_user_by_name("fred").is_none());
+        assert_eq!(&*overlay.get_current_username().unwrap(), "freddie");
+        assert_eq!(overlay.all_users().count(), 3);
+    }
+
+    #[test]
+    fn hidden() {
+        let mut overlay = Overlay::new(underneath());
+        overlay.hide_user(1001);
+        overlay.hide_group(0);
+
+        assert!(overlay.get_user_by_uid(1001).is_none());
+        assert!(overlay.get_user_by_name("bob").is_none());
+        assert!(overlay.try_get_user_by_uid(1001).unwrap().is_none());
+        assert!(overlay.get_group_by_name("root").is_none());
+        assert_eq!(uids(overlay.all_users().collect()), vec![ 0, 1000 ]);
+
+        overlay.clear();
+        assert!(overlay.get_user_by_name("bob").is_some());
+    }
+
+    #[test]
+    fn ids() {
+        let overlay = Overlay::new(underneath())
+            .with_effective_uid(0)
+            .with_current_gid(0);
+
+        assert_eq!(overlay.get_current_uid(), 1000);
+        assert_eq!(&*overlay.get_effective_username().unwrap(), "root");
+        assert_eq!(&*overlay.get_current_groupname().unwrap(), "root");
+        assert_eq!(overlay.get_effective_gid(), 100);
+    }
+
+    #[test]
+    fn memberships() {
+        let mut overlay = Overlay::new(underneath());
+        overlay.add_user(User::new(2000, "tester", 100));
+        overlay.add_group(Group::new(200, "testers").add_member("tester").add_member("fred"));
+        overlay.hide_user(1001);
+
+        assert_eq!(uids(overlay.get_group_members(100).unwrap()), vec![ 0, 1000, 2000 ]);
+        assert_eq!(uids(overlay.get_group_members(200).unwrap()), vec![ 1000, 2000 ]);
+
+        let gids = overlay.get_user_groups("fred", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 100, 200 ]);
+
+        // Replacing a group keeps only the users whose primary group it is.
+        overlay.add_group(Group::new(100, "staff"));
+        assert_eq!(uids(overlay.get_group_members(100).unwrap()), vec![ 1000, 2000 ]);
+        let gids = overlay.get_user_groups("root", 0).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 0 ]);
+    }
+
+    #[test]
+    fn memberships_of_hidden_users() {
+        let mut overlay = Overlay::new(underneath());
+        overlay.add_group(Group::new(200, "testers").add_member("fred").add_member("bob"));
+        overlay.hide_user(1001);
+        overlay.add_user(User::new(1000, "freddie", 100));
+
+        assert!(overlay.get_user_groups("bob", 100).is_none());
+        assert!(overlay.get_user_groups("fred", 100).is_none());
+        assert_eq!(overlay.get_user_groups("freddie", 100).unwrap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cache")]
+    fn over_cache() {
+        let mut overlay = Overlay::new(::cache::UsersCache::new());
+        overlay.hide_user(0);
+        assert!(overlay.get_user_by_uid(0).is_none());
+        assert!(overlay.into_inner().get_user_by_uid(0).is_some());
+    }
+}
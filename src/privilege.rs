@@ -0,0 +1,301 @@
+//! Functions for programs that run with elevated privileges.
+//!
+//! When a program gets run through `sudo` or a similar tool, the process
+//! belongs to the target user — usually root — so functions such as
+//! [`get_current_username`](../fn.get_current_username.html) report that
+//! user, rather than the person who typed the command. The
+//! [`invoking_user`](fn.invoking_user.html) function looks for the traces
+//! these tools leave behind to find out who that person was.
+//!
+//! Programs that are installed setuid or setgid have the opposite problem:
+//! they run with the privileges of their owner, but in an environment
+//! controlled by whoever ran them. The
+//! [`is_privileged_exec`](fn.is_privileged_exec.html) and
+//! [`sanitise_environment`](fn.sanitise_environment.html) functions let these
+//! programs notice this and protect themselves.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+
+use libc::uid_t;
+
+use base::{User, get_user_by_uid, get_user_by_name};
+use base::{get_current_uid, get_effective_uid, get_current_gid, get_effective_gid};
+
+
+/// The mechanism through which a process was started by another user.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Elevation {
+
+    /// The process was started with `sudo`, which sets the `SUDO_UID` and
+    /// `SUDO_USER` environment variables.
+    Sudo,
+
+    /// The process was started with `doas`, which sets the `DOAS_USER`
+    /// environment variable.
+    Doas,
+
+    /// The process was started with `pkexec`, which sets the `PKEXEC_UID`
+    /// environment variable.
+    Pkexec,
+
+    /// The process belongs to a login session started by a different user,
+    /// according to the kernel’s audit login UID. This is how `su` and any
+    /// other tools that leave no environment variables get detected.
+    LoginUid,
+}
+
+/// Returns the user who started the current process through `sudo`, `doas`,
+/// `pkexec`, or `su`, along with which of these was used. Returns `None` if
+/// there is no sign of any of them, or if the user they name is the one
+/// already running the process.
+///
+/// Each user that gets found is checked against the users database, so a
+/// user that doesn’t exist will never be returned.
+///
+/// The login UID is only available on Linux systems with auditing support,
+/// and is checked last, as it refers to the user who logged in, rather than
+/// the one who most recently switched users.
+///
+/// # Security considerations
+///
+/// Apart from the login UID, everything this function checks comes from
+/// environment variables, which the process that started this one could have
+/// set to anything. The result is fine for deciding where to write a
+/// configuration file, but should *never* be used to decide what a user is
+/// allowed to do.
+///
+/// # libc functions used
+///
+/// - [`getuid`](https://docs.rs/libc/*/libc/fn.getuid.html)
+/// - [`getpwuid_r`](https://docs.rs/libc/*/libc/fn.getpwuid_r.html)
+/// - [`getpwnam_r`](https://docs.rs/libc/*/libc/fn.getpwnam_r.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::privilege::invoking_user;
+///
+/// match invoking_user() {
+///     Some((user, via)) => println!("Started by {:?} through {:?}", user.name(), via),
+///     None              => println!("Not started by another user"),
+/// }
+/// ```
+pub fn invoking_user() -> Option<(User, Elevation)> {
+    invoking_user_from(|name| env::var_os(name), login_uid(), get_current_uid())
+}
+
+fn invoking_user_from<F>(getenv: F, login_uid: Option<uid_t>, current_uid: uid_t) -> Option<(User, Elevation)>
+where F: Fn(&str) -> Option<OsString>
+{
+    let parse_uid = |name| getenv(name).and_then(|v| v.to_str()?.parse::<uid_t>().ok());
+    let found = |user: Option<User>, via| user.filter(|u| u.uid() != current_uid).map(|u| (u, via));
+
+    let sudo = || {
+        parse_uid("SUDO_UID").and_then(get_user_by_uid).filter(|user| {
+            // If sudo gave a name as well as an ID, they have to agree.
+            match getenv("SUDO_USER") {
+                Some(name) => name == user.name(),
+                None       => true,
+            }
+        })
+    };
+
+    found(sudo(), Elevation::Sudo)
+        .or_else(|| found(getenv("DOAS_USER").and_then(|n| get_user_by_name(&n)), Elevation::Doas))
+        .or_else(|| found(parse_uid("PKEXEC_UID").and_then(get_user_by_uid), Elevation::Pkexec))
+        .or_else(|| found(login_uid.and_then(get_user_by_uid), Elevation::LoginUid))
+}
+
+/// Reads the audit login UID of the current process, returning `None` if it
+/// has not been set or is not supported.
+fn login_uid() -> Option<uid_t> {
+    let text = fs::read_to_string("/proc/self/loginuid").ok()?;
+
+    match text.trim().parse::<uid_t>() {
+        Ok(uid) if uid != !0 => Some(uid),
+        _                    => None,
+    }
+}
+
+
+/// The `PATH` that `sanitise_environment` sets.
+const SECURE_PATH: &str = "/usr/bin:/bin:/usr/sbin:/sbin";
+
+/// The environment variables that glibc removes when running a program in
+/// secure mode, plus a few that are dangerous to shells.
+static UNSECURE_VARS: &[&str] = &[
+    "GCONV_PATH", "GETCONF_DIR", "GLIBC_TUNABLES", "HOSTALIASES",
+    "LOCALDOMAIN", "LOCPATH", "MALLOC_TRACE", "NIS_PATH", "NLSPATH",
+    "RESOLV_HOST_CONF", "RES_OPTIONS", "TMPDIR", "TZDIR",
+    "IFS", "ENV", "BASH_ENV",
+];
+
+/// The prefixes of environment variables that get removed, whatever comes
+/// after them: those for the dynamic linker and the memory allocator.
+static UNSECURE_PREFIXES: &[&str] = &["LD_", "MALLOC_"];
+
+/// Returns whether the current process is running with privileges that the
+/// user who started it does not have, such as a program that is installed
+/// setuid or setgid.
+///
+/// On Linux, this asks the kernel whether the program was started in
+/// “secure mode”, which also covers file capabilities and security modules.
+/// Everywhere else, or if the kernel doesn’t say so, it compares the real and
+/// effective user and group IDs.
+///
+/// # libc functions used
+///
+/// - [`getauxval`](https://docs.rs/libc/*/libc/fn.getauxval.html), on Linux
+/// - [`getuid`](https://docs.rs/libc/*/libc/fn.getuid.html)
+/// - [`geteuid`](https://docs.rs/libc/*/libc/fn.geteuid.html)
+/// - [`getgid`](https://docs.rs/libc/*/libc/fn.getgid.html)
+/// - [`getegid`](https://docs.rs/libc/*/libc/fn.getegid.html)
+///
+/// # Examples
+///
+/// ```
+/// use users::privilege::{is_privileged_exec, sanitise_environment};
+///
+/// if is_privileged_exec() {
+///     sanitise_environment();
+/// }
+/// ```
+pub fn is_privileged_exec() -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if unsafe { libc::getauxval(libc::AT_SECURE) } != 0 {
+            return true;
+        }
+    }
+
+    get_current_uid() != get_effective_uid() || get_current_gid() != get_effective_gid()
+}
+
+/// Returns whether the environment variable with the given name is one that
+/// `sanitise_environment` removes.
+///
+/// # Examples
+///
+/// ```
+/// use users::privilege::is_unsafe_env_var;
+///
+/// assert!(is_unsafe_env_var("LD_PRELOAD"));
+/// assert!(!is_unsafe_env_var("LANG"));
+/// ```
+pub fn is_unsafe_env_var<S: AsRef<OsStr> + ?Sized>(name: &S) -> bool {
+    let name = name.as_ref().as_bytes();
+
+    UNSECURE_VARS.iter().any(|v| v.as_bytes() == name)
+        || UNSECURE_PREFIXES.iter().any(|p| name.starts_with(p.as_bytes()))
+}
+
+/// Removes environment variables that could be used to subvert a privileged
+/// program from the current process’s environment, and resets `PATH` to a
+/// fixed list of system directories.
+///
+/// The variables that get removed are the ones glibc ignores when it runs a
+/// program in secure mode — every variable beginning with `LD_` or
+/// `MALLOC_`, plus ones such as `GCONV_PATH`, `NLSPATH`, and `TMPDIR` —
+/// along with `IFS`, `ENV`, and `BASH_ENV`, which change the behaviour of any
+/// shell the program runs.
+///
+/// This changes the environment of the whole process, so it should be called
+/// at the start of `main`, before any other threads get spawned.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::privilege::sanitise_environment;
+///
+/// sanitise_environment();
+/// assert!(std::env::var_os("LD_PRELOAD").is_none());
+/// ```
+pub fn sanitise_environment() {
+    let unsafe_vars = env::vars_os()
+                          .map(|(name, _)| name)
+                          .filter(is_unsafe_env_var)
+                          .collect::<Vec<_>>();
+
+    for name in unsafe_vars {
+        env::remove_var(name);
+    }
+
+    env::set_var("PATH", SECURE_PATH);
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsStr;
+
+    // These tests look up root, as that’s the only user guaranteed to exist.
+    // They pretend the current user is someone else.
+    const CURRENT: uid_t = 31337;
+
+    fn env_of(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<OsString> {
+        move |name| vars.iter().find(|&&(n, _)| n == name).map(|&(_, v)| OsString::from(v))
+    }
+
+    #[test]
+    fn nothing() {
+        assert!(invoking_user_from(env_of(&[]), None, CURRENT).is_none());
+    }
+
+    #[test]
+    fn sudo() {
+        let (user, via) = invoking_user_from(env_of(&[("SUDO_UID", "0"), ("SUDO_USER", "root")]), None, CURRENT).unwrap();
+        assert_eq!(user.uid(), 0);
+        assert_eq!(via, Elevation::Sudo);
+    }
+
+    #[test]
+    fn sudo_mismatched_name() {
+        assert!(invoking_user_from(env_of(&[("SUDO_UID", "0"), ("SUDO_USER", "not-root")]), None, CURRENT).is_none());
+    }
+
+    #[test]
+    fn doas() {
+        let (user, via) = invoking_user_from(env_of(&[("DOAS_USER", "root")]), None, CURRENT).unwrap();
+        assert_eq!(user.name(), OsStr::new("root"));
+        assert_eq!(via, Elevation::Doas);
+    }
+
+    #[test]
+    fn pkexec_garbage() {
+        assert!(invoking_user_from(env_of(&[("PKEXEC_UID", "zero")]), None, CURRENT).is_none());
+    }
+
+    #[test]
+    fn login_uid() {
+        let (user, via) = invoking_user_from(env_of(&[]), Some(0), CURRENT).unwrap();
+        assert_eq!(user.uid(), 0);
+        assert_eq!(via, Elevation::LoginUid);
+    }
+
+    #[test]
+    fn same_user() {
+        assert!(invoking_user_from(env_of(&[("SUDO_UID", "0")]), Some(0), 0).is_none());
+    }
+
+    #[test]
+    fn unsafe_vars() {
+        assert!(is_unsafe_env_var("LD_LIBRARY_PATH"));
+        assert!(is_unsafe_env_var("LD_ANYTHING_ELSE"));
+        assert!(is_unsafe_env_var("MALLOC_CHECK_"));
+        assert!(is_unsafe_env_var("GCONV_PATH"));
+        assert!(is_unsafe_env_var("IFS"));
+    }
+
+    #[test]
+    fn safe_vars() {
+        assert!(!is_unsafe_env_var("HOME"));
+        assert!(!is_unsafe_env_var("TERM"));
+        assert!(!is_unsafe_env_var("OLD_PRELOAD"));
+        assert!(!is_unsafe_env_var("ld_preload"));
+        assert!(!is_unsafe_env_var(""));
+    }
+}
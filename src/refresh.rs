@@ -0,0 +1,359 @@
+//! A cache that keeps itself up to date in the background.
+//!
+//! Both [`UsersCache`](../cache/struct.UsersCache.html) and
+//! [`UsersSnapshot`](../snapshot/struct.UsersSnapshot.html) do their slow
+//! work on the thread that asks for a user or group: the cache queries the
+//! C library on a miss, and the snapshot reads the whole database when it
+//! gets created. On systems that get their users from a directory service,
+//! either can take a long time.
+//!
+//! A [`RefreshingCache`](struct.RefreshingCache.html) moves this work onto a
+//! thread of its own. It reads every user and group into a snapshot when it
+//! gets created, then replaces that snapshot with a fresh one in the
+//! background, either on an interval or when the database files change.
+//! Lookups only ever read whichever snapshot is current, so they don’t wait
+//! for a refresh to finish.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::time::Duration;
+//! use users::{Users, Groups};
+//! use users::refresh::{RefreshingCache, RefreshPolicy};
+//!
+//! let cache = RefreshingCache::new(RefreshPolicy::Interval(Duration::from_secs(600)))
+//!     .expect("Failed to start refresh thread");
+//!
+//! let root = cache.get_user_by_uid(0);
+//! ```
+
+use std::ffi::OsStr;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use libc::{uid_t, gid_t};
+
+use base::{User, Group};
+use cache::{UsersCache, FileWatch, PASSWD_PATH, GROUP_PATH, read, write, lock};
+use snapshot::UsersSnapshot;
+use traits::{Users, Groups};
+
+
+/// The shortest amount of time a `RefreshingCache` waits between refreshes.
+///
+/// Reading the whole database is slow, so a refresh policy with a shorter
+/// period than this, such as zero, gets this one instead, rather than
+/// keeping the background thread busy forever.
+pub const MIN_PERIOD: Duration = Duration::from_secs(1);
+
+/// When a `RefreshingCache` reads the users and groups databases again.
+///
+/// Periods shorter than [`MIN_PERIOD`](constant.MIN_PERIOD.html) are
+/// rounded up to it.
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum RefreshPolicy {
+
+    /// Every time the given amount of time has passed.
+    Interval(Duration),
+
+    /// Whenever `/etc/passwd` or `/etc/group` gets modified, checking their
+    /// modification times every time the given amount of time has passed.
+    ///
+    /// This only notices changes to the local files, not to users and groups
+    /// that come from somewhere else, such as LDAP.
+    FileChange(Duration),
+}
+
+impl RefreshPolicy {
+
+    /// Returns how long to wait between checks, no shorter than the minimum.
+    fn period(self) -> Duration {
+        let period = match self {
+            RefreshPolicy::Interval(period)   => period,
+            RefreshPolicy::FileChange(period) => period,
+        };
+
+        period.max(MIN_PERIOD)
+    }
+}
+
+/// A producer of users and groups that answers lookups from a snapshot of
+/// the whole database, which a background thread keeps up to date.
+///
+/// Users and groups that aren’t in the current snapshot — because they were
+/// created since it was taken, or because the system doesn’t list every user
+/// when asked for all of them, as is common with directory services — get
+/// looked up synchronously, and the result kept in a
+/// [`UsersCache`](../cache/struct.UsersCache.html) until the next refresh.
+///
+/// Each refresh builds a new snapshot without touching the old one, then
+/// swaps it in all at once, so a lookup never sees a half-refreshed table.
+/// Values already returned from an old snapshot stay valid.
+///
+/// Listing every user or group lists the ones in the current snapshot.
+///
+/// Dropping the cache stops the background thread, waiting for any refresh
+/// that’s in progress to finish.
+pub struct RefreshingCache {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// The parts of a `RefreshingCache` that the background thread uses too.
+struct Shared {
+    snapshot: RwLock<Arc<UsersSnapshot>>,
+    fallback: UsersCache,
+
+    stopped: Mutex<bool>,
+    wakeup: Condvar,
+}
+
+impl RefreshingCache {
+
+    /// Reads every user and group present on the system, then starts a
+    /// thread that reads them again according to the given policy.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getpwent`](https://docs.rs/libc/*/libc/fn.getpwent.html)
+    /// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+    ///
+    /// # Errors
+    ///
+    /// This function will return `Err` if the thread cannot be started.
+    pub fn new(policy: RefreshPolicy) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            snapshot: RwLock::new(Arc::new(UsersSnapshot::new())),
+            fallback: UsersCache::new(),
+
+            stopped: Mutex::new(false),
+            wakeup: Condvar::new(),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::Builder::new()
+            .name("users-refresh".into())
+            .spawn(move || thread_shared.run(policy))?;
+
+        Ok(Self { shared, thread: Some(thread) })
+    }
+
+    /// Returns the snapshot that lookups are currently being answered from.
+    pub fn snapshot(&self) -> Arc<UsersSnapshot> {
+        Arc::clone(&read(&self.shared.snapshot))
+    }
+
+    /// Reads the users and groups databases again on the current thread,
+    /// without waiting for the background thread to do it.
+    pub fn refresh(&self) {
+        self.shared.refresh();
+    }
+}
+
+impl Shared {
+    fn refresh(&self) {
+        let snapshot = Arc::new(UsersSnapshot::new());
+        *write(&self.snapshot) = snapshot;
+        self.fallback.clear();
+    }
+
+    /// The background thread’s loop, which sleeps until it’s time to check
+    /// for a refresh, and exits once the cache has been dropped.
+    fn run(&self, policy: RefreshPolicy) {
+        let period = policy.period();
+        let watches = match policy {
+            RefreshPolicy::Interval(_)   => None,
+            RefreshPolicy::FileChange(_) => Some((FileWatch::new(PASSWD_PATH), FileWatch::new(GROUP_PATH))),
+        };
+
+        let mut last_check = Instant::now();
+        loop {
+            {
+                let stopped = lock(&self.stopped);
+                if *stopped {
+                    return;
+                }
+
+                // Waiting can end early, so go round again to check whether
+                // it’s really time yet.
+                let elapsed = last_check.elapsed();
+                if elapsed < period {
+                    let _ = self.wakeup.wait_timeout(stopped, period - elapsed);
+                    continue;
+                }
+            }
+
+            last_check = Instant::now();
+            let due = match watches {
+                Some((ref passwd, ref group)) => {
+                    let passwd_changed = passwd.has_changed();
+                    let group_changed = group.has_changed();
+                    passwd_changed || group_changed
+                }
+                None => true,
+            };
+
+            if due {
+                self.refresh();
+            }
+        }
+    }
+}
+
+impl Drop for RefreshingCache {
+    fn drop(&mut self) {
+        *lock(&self.shared.stopped) = true;
+        self.shared.wakeup.notify_all();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Users for RefreshingCache {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.snapshot().get_user_by_uid(uid)
+            .or_else(|| self.shared.fallback.get_user_by_uid(uid))
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.snapshot().get_user_by_name(username)
+            .or_else(|| self.shared.fallback.get_user_by_name(username))
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.shared.fallback.get_current_uid()
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_current_uid();
+        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.shared.fallback.get_effective_uid()
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        let uid = self.get_effective_uid();
+        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let snapshot = self.snapshot();
+        if snapshot.get_user_by_name(username).is_some() {
+            snapshot.get_user_groups(username, gid)
+        }
+        else {
+            self.shared.fallback.get_user_groups(username, gid)
+        }
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let snapshot = self.snapshot();
+        Box::new(snapshot.users().cloned().collect::<Vec<_>>().into_iter())
+    }
+}
+
+impl Groups for RefreshingCache {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.snapshot().get_group_by_gid(gid)
+            .or_else(|| self.shared.fallback.get_group_by_gid(gid))
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.snapshot().get_group_by_name(group_name)
+            .or_else(|| self.shared.fallback.get_group_by_name(group_name))
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.shared.fallback.get_current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_current_gid();
+        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.shared.fallback.get_effective_gid()
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        let gid = self.get_effective_gid();
+        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        self.snapshot().get_group_members(gid)
+            .or_else(|| self.shared.fallback.get_group_members(gid))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let snapshot = self.snapshot();
+        Box::new(snapshot.groups().cloned().collect::<Vec<_>>().into_iter())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RefreshingCache>();
+    }
+
+    #[test]
+    fn lookups() {
+        let cache = RefreshingCache::new(RefreshPolicy::Interval(HOUR)).unwrap();
+        assert_eq!(cache.get_user_by_uid(0).unwrap().uid(), 0);
+        assert!(cache.get_group_by_gid(0).is_some());
+        assert!(cache.get_user_by_name("no-such-user\0").is_none());
+    }
+
+    #[test]
+    fn refresh_swaps() {
+        let cache = RefreshingCache::new(RefreshPolicy::Interval(HOUR)).unwrap();
+        let before = cache.snapshot();
+        let root = cache.get_user_by_uid(0).unwrap();
+
+        cache.refresh();
+        assert!(!Arc::ptr_eq(&before, &cache.snapshot()));
+        assert!(!Arc::ptr_eq(&root, &cache.get_user_by_uid(0).unwrap()));
+    }
+
+    #[test]
+    fn minimum_period() {
+        assert_eq!(RefreshPolicy::Interval(Duration::from_secs(0)).period(), MIN_PERIOD);
+        assert_eq!(RefreshPolicy::FileChange(Duration::from_millis(10)).period(), MIN_PERIOD);
+        assert_eq!(RefreshPolicy::Interval(HOUR).period(), HOUR);
+    }
+
+    #[test]
+    fn refreshes_in_background() {
+        // This waits for the minimum period, rather than refreshing over and
+        // over again in a busy loop.
+        let cache = RefreshingCache::new(RefreshPolicy::Interval(Duration::from_secs(0))).unwrap();
+        let before = cache.snapshot();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Arc::ptr_eq(&before, &cache.snapshot()) {
+            assert!(Instant::now() < deadline, "Snapshot was never refreshed");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn drop_stops_thread() {
+        // This would wait for an hour if dropping didn’t wake the thread up.
+        drop(RefreshingCache::new(RefreshPolicy::FileChange(HOUR)).unwrap());
+    }
+}
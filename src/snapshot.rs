@@ -0,0 +1,469 @@
+//! A point-in-time copy of the whole users and groups databases.
+//!
+//! A [`UsersCache`](../cache/struct.UsersCache.html) only looks users and
+//! groups up when they’re asked for, so a program that runs for a while may
+//! see some from before a change to the databases and some from after it.
+//! A [`UsersSnapshot`](struct.UsersSnapshot.html) reads every user and group
+//! once, when it gets created, and never changes afterwards. As it has
+//! nothing to update, it needs no locks: it can be shared between threads by
+//! putting it in an `Arc`, and every lookup is a single hash table access.
+//!
+//! ## Example
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::thread;
+//! use users::{Users, UsersSnapshot};
+//!
+//! let snapshot = Arc::new(UsersSnapshot::new());
+//!
+//! let other = Arc::clone(&snapshot);
+//! thread::spawn(move || {
+//!     for user in other.users() {
+//!         println!("User #{} ({:?})", user.uid(), user.name());
+//!     }
+//! }).join().unwrap();
+//!
+//! let root = snapshot.get_user_by_uid(0);
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::slice;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use libc::{uid_t, gid_t};
+
+use base::{User, Group, read_all_users, read_all_groups};
+use base::{get_current_uid, get_effective_uid, get_current_gid, get_effective_gid};
+use base::os::unix::GroupExt;
+use traits::{Users, Groups};
+
+
+/// An immutable copy of every user and group on the system.
+///
+/// Users and groups can be looked up by ID or name, iterated over in order
+/// of their IDs, and matched up with each other through
+/// [`groups_of_user`](#method.groups_of_user) and
+/// [`members_of_group`](#method.members_of_group).
+///
+/// If more than one user has the same ID or name, lookups return whichever
+/// one came first in the database, the same as the C library does.
+/// Iterating returns all of them.
+///
+/// The current and effective user and group IDs are read when the snapshot
+/// is taken, too, and don’t change even if the process switches users
+/// afterwards.
+pub struct UsersSnapshot {
+    users: Vec<Arc<User>>,
+    groups: Vec<Arc<Group>>,
+
+    users_by_uid: HashMap<uid_t, usize>,
+    users_by_name: HashMap<Arc<OsStr>, usize>,
+    groups_by_gid: HashMap<gid_t, usize>,
+    groups_by_name: HashMap<Arc<OsStr>, usize>,
+
+    /// The groups each username is listed as a member of, not counting
+    /// primary groups.
+    user_groups: HashMap<Arc<OsStr>, Vec<usize>>,
+
+    /// The users that are members of each group, counting primary groups,
+    /// with the same indices as `groups`.
+    group_members: Vec<Vec<usize>>,
+
+    uid: uid_t,
+    gid: gid_t,
+    euid: uid_t,
+    egid: gid_t,
+
+    taken_at: SystemTime,
+}
+
+impl UsersSnapshot {
+
+    /// Reads every user and group present on the system into a new
+    /// snapshot.
+    ///
+    /// Like [`UsersCache::with_all_users_and_groups`](../cache/struct.UsersCache.html#method.with_all_users_and_groups),
+    /// this waits for any other iteration over the users or groups database
+    /// by this crate to finish first.
+    ///
+    /// # libc functions used
+    ///
+    /// - [`getpwent`](https://docs.rs/libc/*/libc/fn.getpwent.html)
+    /// - [`getgrent`](https://docs.rs/libc/*/libc/fn.getgrent.html)
+    /// - [`getuid`](https://docs.rs/libc/*/libc/fn.getuid.html)
+    /// - [`geteuid`](https://docs.rs/libc/*/libc/fn.geteuid.html)
+    /// - [`getgid`](https://docs.rs/libc/*/libc/fn.getgid.html)
+    /// - [`getegid`](https://docs.rs/libc/*/libc/fn.getegid.html)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::UsersSnapshot;
+    ///
+    /// let snapshot = UsersSnapshot::new();
+    /// println!("There are {} users", snapshot.users().len());
+    /// ```
+    pub fn new() -> Self {
+        Self::from_users_and_groups(read_all_users(), read_all_groups())
+    }
+
+    /// Creates a snapshot containing the given users and groups, rather than
+    /// the ones on the system. The current and effective IDs are still read
+    /// from the process.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{User, Group, Users, UsersSnapshot};
+    ///
+    /// let snapshot = UsersSnapshot::from_users_and_groups(
+    ///     vec![ User::new(1000, "fred", 100) ],
+    ///     vec![ Group::new(100, "staff") ],
+    /// );
+    ///
+    /// assert!(snapshot.get_user_by_name("fred").is_some());
+    /// ```
+    pub fn from_users_and_groups<U, G>(users: U, groups: G) -> Self
+    where U: IntoIterator<Item=User>,
+          G: IntoIterator<Item=Group>,
+    {
+        // Sorting is stable, so users with the same ID stay in the order the
+        // database had them in. Names can be shared by users with different
+        // IDs, though, so each user’s position in the database is kept until
+        // they have been indexed by name.
+        let mut users = users.into_iter().map(Arc::new).enumerate().collect::<Vec<_>>();
+        users.sort_by_key(|(_, u)| u.uid());
+
+        let mut groups = groups.into_iter().map(Arc::new).enumerate().collect::<Vec<_>>();
+        groups.sort_by_key(|(_, g)| g.gid());
+
+        let users_by_name = first_by_name(users.iter().map(|(position, u)| (&u.name_arc, *position)));
+        let groups_by_name = first_by_name(groups.iter().map(|(position, g)| (&g.name_arc, *position)));
+
+        let users = users.into_iter().map(|(_, u)| u).collect::<Vec<_>>();
+        let groups = groups.into_iter().map(|(_, g)| g).collect::<Vec<_>>();
+
+        let mut users_by_uid = HashMap::new();
+        for (index, user) in users.iter().enumerate() {
+            users_by_uid.entry(user.uid()).or_insert(index);
+        }
+
+        let mut groups_by_gid = HashMap::new();
+        let mut user_groups = HashMap::<_, Vec<usize>>::new();
+        for (index, group) in groups.iter().enumerate() {
+            groups_by_gid.entry(group.gid()).or_insert(index);
+
+            for member in group.members() {
+                user_groups.entry(Arc::from(member.as_os_str())).or_default().push(index);
+            }
+        }
+
+        let mut group_members = vec![ Vec::new(); groups.len() ];
+        for (index, user) in users.iter().enumerate() {
+            if let Some(&g) = groups_by_gid.get(&user.primary_group_id()) {
+                group_members[g].push(index);
+            }
+        }
+
+        for (g, group) in groups.iter().enumerate() {
+            for member in group.members() {
+                if let Some(&index) = users_by_name.get(member.as_os_str()) {
+                    group_members[g].push(index);
+                }
+            }
+        }
+
+        // User indices are in ID order, so sorting them sorts the users.
+        for members in &mut group_members {
+            members.sort_unstable();
+            members.dedup();
+        }
+
+        Self {
+            users, groups,
+            users_by_uid, users_by_name, groups_by_gid, groups_by_name,
+            user_groups, group_members,
+
+            uid:  get_current_uid(),
+            gid:  get_current_gid(),
+            euid: get_effective_uid(),
+            egid: get_effective_gid(),
+
+            taken_at: SystemTime::now(),
+        }
+    }
+
+    /// Returns an iterator over every user in the snapshot, in order of
+    /// their user IDs.
+    pub fn users(&self) -> slice::Iter<'_, Arc<User>> {
+        self.users.iter()
+    }
+
+    /// Returns an iterator over every group in the snapshot, in order of
+    /// their group IDs.
+    pub fn groups(&self) -> slice::Iter<'_, Arc<Group>> {
+        self.groups.iter()
+    }
+
+    /// Returns the groups the given user is a member of, including their
+    /// primary group, in order of their group IDs.
+    ///
+    /// This is the same list of groups as
+    /// [`get_user_groups`](../fn.get_user_groups.html) returns, only taken
+    /// from the snapshot.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use users::{User, Group, Users, UsersSnapshot};
+    /// use users::os::unix::GroupExt;
+    ///
+    /// let snapshot = UsersSnapshot::from_users_and_groups(
+    ///     vec![ User::new(1000, "fred", 100) ],
+    ///     vec![ Group::new(100, "staff"), Group::new(27, "sudo").add_member("fred") ],
+    /// );
+    ///
+    /// let fred = snapshot.get_user_by_uid(1000).unwrap();
+    /// let gids = snapshot.groups_of_user(&fred).iter().map(|g| g.gid()).collect::<Vec<_>>();
+    /// assert_eq!(gids, vec![ 27, 100 ]);
+    /// ```
+    pub fn groups_of_user(&self, user: &User) -> Vec<Arc<Group>> {
+        self.groups_of(user.name(), user.primary_group_id())
+    }
+
+    fn groups_of(&self, username: &OsStr, gid: gid_t) -> Vec<Arc<Group>> {
+        let mut indices = self.user_groups.get(username).cloned().unwrap_or_default();
+        if let Some(&primary) = self.groups_by_gid.get(&gid) {
+            indices.push(primary);
+        }
+
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| Arc::clone(&self.groups[i])).collect()
+    }
+
+    /// Returns the users that are members of the given group, either because
+    /// it’s their primary group or because they are listed in it, in order
+    /// of their user IDs. Listed members that aren’t users in the snapshot
+    /// are left out.
+    pub fn members_of_group(&self, group: &Group) -> Vec<Arc<User>> {
+        match self.groups_by_gid.get(&group.gid()) {
+            Some(&g) if self.groups[g].name() == group.name() => {
+                self.group_members[g].iter().map(|&i| Arc::clone(&self.users[i])).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the time the snapshot was taken.
+    pub fn taken_at(&self) -> SystemTime {
+        self.taken_at
+    }
+}
+
+/// Maps each name to the index of the entry with that name that came first in
+/// the database, given every entry’s name and position in the database, in
+/// the order they are stored in.
+fn first_by_name<'a, I>(entries: I) -> HashMap<Arc<OsStr>, usize>
+where I: Iterator<Item=(&'a Arc<OsStr>, usize)>,
+{
+    let mut first = HashMap::<Arc<OsStr>, (usize, usize)>::new();
+
+    for (index, (name, position)) in entries.enumerate() {
+        let entry = first.entry(Arc::clone(name)).or_insert((position, index));
+        if position < entry.0 {
+            *entry = (position, index);
+        }
+    }
+
+    first.into_iter().map(|(name, (_, index))| (name, index)).collect()
+}
+
+impl Default for UsersSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Users for UsersSnapshot {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.users_by_uid.get(&uid).map(|&i| Arc::clone(&self.users[i]))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        Some(self.groups_of(username.as_ref(), gid))
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        Box::new(self.users.iter().cloned())
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        self.users_by_name.get(username.as_ref()).map(|&i| Arc::clone(&self.users[i]))
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        self.uid
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_by_uid(self.uid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        self.euid
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.get_user_by_uid(self.euid).map(|u| Arc::clone(&u.name_arc))
+    }
+}
+
+impl Groups for UsersSnapshot {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.groups_by_gid.get(&gid).map(|&i| Arc::clone(&self.groups[i]))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let &g = self.groups_by_gid.get(&gid)?;
+        Some(self.group_members[g].iter().map(|&i| Arc::clone(&self.users[i])).collect())
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        Box::new(self.groups.iter().cloned())
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        self.groups_by_name.get(group_name.as_ref()).map(|&i| Arc::clone(&self.groups[i]))
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        self.gid
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_by_gid(self.gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        self.egid
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_group_by_gid(self.egid).map(|g| Arc::clone(&g.name_arc))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> UsersSnapshot {
+        UsersSnapshot::from_users_and_groups(
+            vec![
+                User::new(1002, "carol", 100),
+                User::new(1001, "bob",   100),
+                User::new(0,    "root",  0),
+                User::new(1001, "bobby", 101),
+            ],
+            vec![
+                Group::new(101, "bobs").add_member("bob"),
+                Group::new(100, "staff").add_member("root").add_member("nobody"),
+                Group::new(0,   "root"),
+            ],
+        )
+    }
+
+    fn uids(users: &[Arc<User>]) -> Vec<uid_t> {
+        users.iter().map(|u| u.uid()).collect()
+    }
+
+    #[test]
+    fn send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<UsersSnapshot>();
+    }
+
+    #[test]
+    fn lookups() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.get_user_by_name("carol").unwrap().uid(), 1002);
+        assert_eq!(snapshot.get_group_by_gid(101).unwrap().name(), OsStr::new("bobs"));
+        assert_eq!(snapshot.get_group_by_name("staff").unwrap().gid(), 100);
+        assert!(snapshot.get_user_by_uid(1003).is_none());
+        assert!(snapshot.get_group_by_name("nobody").is_none());
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.get_user_by_uid(1001).unwrap().name(), OsStr::new("bob"));
+        assert_eq!(snapshot.get_user_by_name("bobby").unwrap().uid(), 1001);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let snapshot = UsersSnapshot::from_users_and_groups(
+            vec![ User::new(1001, "dupe", 100), User::new(0, "dupe", 0) ],
+            vec![ Group::new(100, "dupes"), Group::new(0, "dupes") ],
+        );
+
+        assert_eq!(snapshot.get_user_by_name("dupe").unwrap().uid(), 1001);
+        assert_eq!(snapshot.get_group_by_name("dupes").unwrap().gid(), 100);
+    }
+
+    #[test]
+    fn ordered() {
+        let snapshot = snapshot();
+        let names = snapshot.users().map(|u| u.name().to_os_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec![ "root", "bob", "bobby", "carol" ]);
+
+        let gids = snapshot.groups().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 0, 100, 101 ]);
+    }
+
+    #[test]
+    fn groups_of_user() {
+        let snapshot = snapshot();
+        let bob = snapshot.get_user_by_name("bob").unwrap();
+        let gids = snapshot.groups_of_user(&bob).iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 100, 101 ]);
+    }
+
+    #[test]
+    fn members_of_group() {
+        let snapshot = snapshot();
+        let staff = snapshot.get_group_by_gid(100).unwrap();
+        assert_eq!(uids(&snapshot.members_of_group(&staff)), vec![ 0, 1001, 1002 ]);
+
+        let bobs = snapshot.get_group_by_gid(101).unwrap();
+        assert_eq!(uids(&snapshot.members_of_group(&bobs)), vec![ 1001, 1001 ]);
+
+        assert!(snapshot.members_of_group(&Group::new(100, "not-staff")).is_empty());
+    }
+
+    #[test]
+    fn trait_memberships() {
+        let snapshot = snapshot();
+        let gids = snapshot.get_user_groups("bob", 100).unwrap().iter().map(|g| g.gid()).collect::<Vec<_>>();
+        assert_eq!(gids, vec![ 100, 101 ]);
+
+        assert_eq!(uids(&snapshot.get_group_members(100).unwrap()), vec![ 0, 1001, 1002 ]);
+        assert!(snapshot.get_group_members(102).is_none());
+
+        let names = snapshot.all_users().map(|u| u.name().to_os_string()).collect::<Vec<_>>();
+        assert_eq!(names, vec![ "root", "bob", "bobby", "carol" ]);
+        assert_eq!(snapshot.all_groups().count(), 3);
+    }
+
+    #[test]
+    fn system() {
+        let snapshot = UsersSnapshot::new();
+        assert!(snapshot.get_user_by_uid(0).is_some());
+        assert_eq!(snapshot.get_current_uid(), get_current_uid());
+    }
+}
@@ -1,6 +1,7 @@
 //! Functions for switching the running process’s user or group.
 
 use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use libc::{uid_t, gid_t, c_int};
 
 use base::{get_effective_uid, get_effective_gid};
@@ -8,12 +9,30 @@ use base::{get_effective_uid, get_effective_gid};
 
 // NOTE: for whatever reason, it seems these are not available in libc on BSD platforms, so they
 //       need to be included manually
-extern {
+extern "C" {
     fn setreuid(ruid: uid_t, euid: uid_t) -> c_int;
     fn setregid(rgid: gid_t, egid: gid_t) -> c_int;
 }
 
 
+/// A counter that goes up every time one of the functions in this module
+/// changes the running process’s user or group IDs, so that anything that
+/// stores those IDs can tell when it needs to read them again.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the current value of the credentials counter.
+#[cfg(feature = "cache")]
+pub(crate) fn credentials_generation() -> usize {
+    GENERATION.load(Ordering::SeqCst)
+}
+
+/// Increases the credentials counter. This gets called whether or not the
+/// change succeeded, as a failed call may still have changed something.
+fn credentials_changed() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+
 /// Sets the **current user** for the running process to the one with the
 /// given user ID.
 ///
@@ -38,7 +57,10 @@ extern {
 /// // current user ID is 1001
 /// ```
 pub fn set_current_uid(uid: uid_t) -> io::Result<()> {
-    match unsafe { libc::setuid(uid) } {
+    let result = unsafe { libc::setuid(uid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("setuid returned {}", n)
@@ -69,7 +91,10 @@ pub fn set_current_uid(uid: uid_t) -> io::Result<()> {
 /// // current group ID is 1001
 /// ```
 pub fn set_current_gid(gid: gid_t) -> io::Result<()> {
-    match unsafe { libc::setgid(gid) } {
+    let result = unsafe { libc::setgid(gid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("setgid returned {}", n)
@@ -100,7 +125,10 @@ pub fn set_current_gid(gid: gid_t) -> io::Result<()> {
 /// // current effective user ID is 1001
 /// ```
 pub fn set_effective_uid(uid: uid_t) -> io::Result<()> {
-    match unsafe { libc::seteuid(uid) } {
+    let result = unsafe { libc::seteuid(uid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("seteuid returned {}", n)
@@ -131,7 +159,10 @@ pub fn set_effective_uid(uid: uid_t) -> io::Result<()> {
 /// // current effective group ID is 1001
 /// ```
 pub fn set_effective_gid(gid: gid_t) -> io::Result<()> {
-    match unsafe { libc::setegid(gid) } {
+    let result = unsafe { libc::setegid(gid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("setegid returned {}", n)
@@ -162,7 +193,10 @@ pub fn set_effective_gid(gid: gid_t) -> io::Result<()> {
 /// // current user ID and effective user ID are 1001
 /// ```
 pub fn set_both_uid(ruid: uid_t, euid: uid_t) -> io::Result<()> {
-    match unsafe { setreuid(ruid, euid) } {
+    let result = unsafe { setreuid(ruid, euid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("setreuid returned {}", n)
@@ -193,7 +227,10 @@ pub fn set_both_uid(ruid: uid_t, euid: uid_t) -> io::Result<()> {
 /// // current user ID and effective group ID are 1001
 /// ```
 pub fn set_both_gid(rgid: gid_t, egid: gid_t) -> io::Result<()> {
-    match unsafe { setregid(rgid, egid) } {
+    let result = unsafe { setregid(rgid, egid) };
+    credentials_changed();
+
+    match result {
          0 => Ok(()),
         -1 => Err(io::Error::last_os_error()),
          n => unreachable!("setregid returned {}", n)
@@ -263,3 +300,217 @@ pub fn switch_user_group(uid: uid_t, gid: gid_t) -> io::Result<SwitchUserGuard>
     set_effective_uid(uid)?;
     Ok(current_state)
 }
+
+/// Sets the **filesystem user** for the running process to the one with the
+/// given user ID, returning the previous filesystem user ID.
+///
+/// The filesystem user ID is what the kernel uses when checking file
+/// permissions, and nothing else: unlike the effective user ID, changing it
+/// does not affect who is allowed to send signals to the process, or who owns
+/// it. This makes it useful for servers that access files on behalf of
+/// another user. It normally follows the effective user ID, so it only needs
+/// setting explicitly when the two should differ.
+///
+/// The underlying system call never reports failure, so this function checks
+/// whether the change took effect by reading the value back afterwards.
+///
+/// This function is only available on Linux.
+///
+/// # libc functions used
+///
+/// - [`setfsuid`](https://docs.rs/libc/*/libc/fn.setfsuid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` with a `PermissionDenied` error if the
+/// filesystem user ID was not changed to the requested value.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::set_fs_uid;
+///
+/// let previous = set_fs_uid(1001).unwrap();
+/// // file permissions are now checked as user 1001
+/// set_fs_uid(previous).unwrap();
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_fs_uid(uid: uid_t) -> io::Result<uid_t> {
+    let previous = unsafe { libc::setfsuid(uid) } as uid_t;
+
+    // Passing an invalid ID changes nothing, and returns the current value.
+    let current = unsafe { libc::setfsuid(!0) } as uid_t;
+
+    if current == uid {
+        Ok(previous)
+    }
+    else {
+        Err(io::Error::from_raw_os_error(libc::EPERM))
+    }
+}
+
+/// Sets the **filesystem group** for the running process to the one with the
+/// given group ID, returning the previous filesystem group ID.
+///
+/// Like the filesystem user ID, this is only used when checking file
+/// permissions. See [`set_fs_uid`](fn.set_fs_uid.html) for more information.
+///
+/// This function is only available on Linux.
+///
+/// # libc functions used
+///
+/// - [`setfsgid`](https://docs.rs/libc/*/libc/fn.setfsgid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` with a `PermissionDenied` error if the
+/// filesystem group ID was not changed to the requested value.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::set_fs_gid;
+///
+/// let previous = set_fs_gid(1001).unwrap();
+/// // file permissions are now checked as group 1001
+/// set_fs_gid(previous).unwrap();
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_fs_gid(gid: gid_t) -> io::Result<gid_t> {
+    let previous = unsafe { libc::setfsgid(gid) } as gid_t;
+
+    // Passing an invalid ID changes nothing, and returns the current value.
+    let current = unsafe { libc::setfsgid(!0) } as gid_t;
+
+    if current == gid {
+        Ok(previous)
+    }
+    else {
+        Err(io::Error::from_raw_os_error(libc::EPERM))
+    }
+}
+
+/// Guard returned from a `switch_fs_user_group` call.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub struct SwitchFsUserGuard {
+    uid: uid_t,
+    gid: gid_t,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl Drop for SwitchFsUserGuard {
+    fn drop(&mut self) {
+        set_fs_gid(self.gid).expect("Failed to set filesystem gid");
+        set_fs_uid(self.uid).expect("Failed to set filesystem uid");
+    }
+}
+
+/// Sets the **filesystem user** and the **filesystem group** for the current
+/// scope.
+///
+/// This only changes which user and group file permissions are checked
+/// against, leaving the effective user and group untouched. It is the
+/// filesystem-only counterpart to
+/// [`switch_user_group`](fn.switch_user_group.html).
+///
+/// This function is only available on Linux.
+///
+/// # Security considerations
+///
+/// - Because Rust does not guarantee running the destructor, it’s a good idea
+///   to call [`std::mem::drop`](https://doc.rust-lang.org/std/mem/fn.drop.html)
+///   on the guard manually in security-sensitive situations.
+/// - The filesystem IDs belong to the calling *thread*, rather than the whole
+///   process, so the guard should be dropped on the same thread that created
+///   it.
+/// - This function switches the group before the user, for the same reasons
+///   as `switch_user_group`.
+/// - The guard will panic upon failing to restore either value.
+///
+/// # libc functions used
+///
+/// - [`setfsuid`](https://docs.rs/libc/*/libc/fn.setfsuid.html)
+/// - [`setfsgid`](https://docs.rs/libc/*/libc/fn.setfsgid.html)
+///
+/// # Errors
+///
+/// This function will return `Err` when either the filesystem user or group
+/// could not be changed. If the group was changed but the user was not, the
+/// group is changed back before returning.
+///
+/// # Examples
+///
+/// ```no_run
+/// use users::switch::switch_fs_user_group;
+/// use std::mem::drop;
+///
+/// {
+///     let guard = switch_fs_user_group(1001, 1001);
+///     // file permissions are checked as user and group 1001
+///     drop(guard);
+/// }
+/// // back to the old values
+/// ```
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn switch_fs_user_group(uid: uid_t, gid: gid_t) -> io::Result<SwitchFsUserGuard> {
+    let old_gid = set_fs_gid(gid)?;
+
+    let old_uid = match set_fs_uid(uid) {
+        Ok(u)  => u,
+        Err(e) => {
+            set_fs_gid(old_gid)?;
+            return Err(e);
+        }
+    };
+
+    Ok(SwitchFsUserGuard { uid: old_uid, gid: old_gid })
+}
+
+
+#[cfg(test)]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod test {
+    use super::*;
+    use libc::{uid_t, gid_t};
+
+    // Passing an invalid ID reads the current value without changing it.
+    fn fs_uid() -> uid_t {
+        unsafe { libc::setfsuid(!0) as uid_t }
+    }
+
+    fn fs_gid() -> gid_t {
+        unsafe { libc::setfsgid(!0) as gid_t }
+    }
+
+    #[test]
+    fn fs_uid_round_trip() {
+        let uid = get_effective_uid();
+        assert_eq!(set_fs_uid(uid).unwrap(), uid);
+        assert_eq!(fs_uid(), uid);
+    }
+
+    #[test]
+    fn fs_gid_round_trip() {
+        let gid = get_effective_gid();
+        assert_eq!(set_fs_gid(gid).unwrap(), gid);
+        assert_eq!(fs_gid(), gid);
+    }
+
+    #[test]
+    fn fs_guard_restores() {
+        let (uid, gid) = (get_effective_uid(), get_effective_gid());
+
+        // Only root can actually switch to someone else, so everyone else
+        // just switches to themselves.
+        let (new_uid, new_gid) = if uid == 0 { (65534, 65534) } else { (uid, gid) };
+
+        {
+            let _guard = switch_fs_user_group(new_uid, new_gid).unwrap();
+            assert_eq!(fs_uid(), new_uid);
+            assert_eq!(fs_gid(), new_gid);
+        }
+
+        assert_eq!(fs_uid(), uid);
+        assert_eq!(fs_gid(), gid);
+    }
+}
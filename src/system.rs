@@ -0,0 +1,205 @@
+//! A producer of users and groups that asks the system every time.
+
+use std::ffi::OsStr;
+use std::io;
+use std::sync::Arc;
+
+use libc::{uid_t, gid_t};
+
+use base::{self, User, Group, read_all_users, read_all_groups};
+use base::{get_bare_user_by_uid, get_bare_group_by_gid};
+use base::os::unix::GroupExt;
+use traits::{Users, Groups};
+
+
+/// A producer of users and groups that looks every one of them up in the
+/// system’s databases, without caching anything.
+///
+/// This is what the free functions in this crate do, behind the `Users` and
+/// `Groups` traits, for code that should be able to work with any producer
+/// but has no use for a cache. Each lookup returns a new value.
+///
+/// Listing every user or group reads the whole database before returning,
+/// in the order the database has them, so nothing else has to wait for the
+/// iterator to be dropped. Finding a group’s members reads the whole users
+/// database too, so, like the other safe functions in this crate that do,
+/// it shouldn’t be called while iterating over
+/// [`all_users`](fn.all_users.html).
+///
+/// # Examples
+///
+/// ```
+/// use users::{Users, SystemUsers};
+///
+/// let users = SystemUsers;
+/// let root = users.get_user_by_uid(0).expect("No root user");
+/// assert_eq!(root.uid(), 0);
+/// ```
+#[derive(PartialEq, Eq, Debug, Default, Copy, Clone)]
+pub struct SystemUsers;
+
+impl Users for SystemUsers {
+    fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        base::get_user_by_uid(uid).map(Arc::new)
+    }
+
+    fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+        base::get_user_by_name(username).map(Arc::new)
+    }
+
+    fn get_current_uid(&self) -> uid_t {
+        base::get_current_uid()
+    }
+
+    fn get_current_username(&self) -> Option<Arc<OsStr>> {
+        base::get_current_username().map(Arc::from)
+    }
+
+    fn get_effective_uid(&self) -> uid_t {
+        base::get_effective_uid()
+    }
+
+    fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+        base::get_effective_username().map(Arc::from)
+    }
+
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        get_bare_user_by_uid(uid).map(|u| u.name_arc)
+    }
+
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        base::try_get_user_by_uid(uid).map(|u| u.map(Arc::new))
+    }
+
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        base::try_get_user_by_name(username).map(|u| u.map(Arc::new))
+    }
+
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        let groups = base::get_user_groups(username, gid)?;
+        Some(groups.into_iter().map(Arc::new).collect())
+    }
+
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        let users = read_all_users().into_iter().map(Arc::new).collect::<Vec<_>>();
+        Box::new(users.into_iter())
+    }
+}
+
+impl Groups for SystemUsers {
+    fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        base::get_group_by_gid(gid).map(Arc::new)
+    }
+
+    fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+        base::get_group_by_name(group_name).map(Arc::new)
+    }
+
+    fn get_current_gid(&self) -> gid_t {
+        base::get_current_gid()
+    }
+
+    fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        base::get_current_groupname().map(Arc::from)
+    }
+
+    fn get_effective_gid(&self) -> gid_t {
+        base::get_effective_gid()
+    }
+
+    fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        base::get_effective_groupname().map(Arc::from)
+    }
+
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        get_bare_group_by_gid(gid).map(|g| g.name_arc)
+    }
+
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        base::try_get_group_by_gid(gid).map(|g| g.map(Arc::new))
+    }
+
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        base::try_get_group_by_name(group_name).map(|g| g.map(Arc::new))
+    }
+
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        let group = base::get_group_by_gid(gid)?;
+        Some(find_group_members(&group, |name| self.get_user_by_name(name), Arc::new))
+    }
+
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        let groups = read_all_groups().into_iter().map(Arc::new).collect::<Vec<_>>();
+        Box::new(groups.into_iter())
+    }
+}
+
+/// Finds the members of the given group: the users listed in its entry,
+/// looked up with the first function, and the users whose primary group it
+/// is, found by iterating over the users database and passed through the
+/// second function. The result is in order of user ID, with each user in it
+/// once.
+pub(crate) fn find_group_members<F, P>(group: &Group, by_name: F, primary: P) -> Vec<Arc<User>>
+where F: Fn(&OsStr) -> Option<Arc<User>>,
+      P: FnMut(User) -> Arc<User>,
+{
+    let mut members = group.members().iter().filter_map(|name| by_name(name)).collect::<Vec<_>>();
+
+    let gid = group.gid();
+    members.extend(read_all_users().into_iter().filter(|u| u.primary_group_id() == gid).map(primary));
+
+    members.sort_by_key(|u| u.uid());
+    members.dedup_by_key(|u| u.uid());
+    members
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookups() {
+        let users = SystemUsers;
+        let uid = users.get_current_uid();
+        let user = users.get_user_by_uid(uid).unwrap();
+
+        assert_eq!(users.get_user_by_name(user.name()).unwrap().uid(), uid);
+        assert_eq!(&*users.get_user_name_by_uid(uid).unwrap(), user.name());
+        assert_eq!(&*users.get_current_username().unwrap(), user.name());
+    }
+
+    #[test]
+    fn memberships() {
+        let users = SystemUsers;
+        let root = users.get_user_by_uid(0).unwrap();
+
+        let groups = users.get_user_groups(root.name(), root.primary_group_id()).unwrap();
+        assert!(groups.iter().any(|g| g.gid() == root.primary_group_id()));
+
+        let members = users.get_group_members(root.primary_group_id()).unwrap();
+        assert!(members.iter().any(|u| u.uid() == 0));
+    }
+
+    #[test]
+    fn members_with_iterator_alive() {
+        // Finding members takes this crate’s own lock, which an iterator from
+        // `all_users` doesn’t hold, so this doesn’t wait forever.
+        let iter = unsafe { base::all_users() };
+        assert!(SystemUsers.get_group_members(0).is_some());
+        drop(iter);
+    }
+
+    #[test]
+    fn listing() {
+        let users = SystemUsers;
+        assert!(users.all_users().any(|u| u.uid() == 0));
+        assert!(users.all_groups().any(|g| g.gid() == 0));
+
+        // Both iterators can be alive at once.
+        let mut all_users = users.all_users();
+        let mut all_groups = users.all_groups();
+        assert!(all_users.next().is_some());
+        assert!(all_groups.next().is_some());
+    }
+}
@@ -1,4 +1,6 @@
 use std::ffi::OsStr;
+use std::io;
+use std::iter;
 use std::sync::Arc;
 
 use libc::{uid_t, gid_t};
@@ -26,6 +28,50 @@ pub trait Users {
 
     /// Returns the effective username.
     fn get_effective_username(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the name of the user with the given user ID, if one exists.
+    ///
+    /// This is for callers that only need the name, such as file listers.
+    /// The default implementation looks up the whole user.
+    fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        self.get_user_by_uid(uid).map(|u| Arc::clone(&u.name_arc))
+    }
+
+    /// Returns the groups that the user with the given name and primary
+    /// group ID is a member of, including the primary group, the same as
+    /// [`get_user_groups`](fn.get_user_groups.html) does.
+    ///
+    /// Groups that don’t exist are left out. Returns `None` if the groups
+    /// couldn’t be looked up, such as when the name contains a null byte, so
+    /// an empty list always means the user is in no groups at all.
+    fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>>;
+
+    /// Returns an iterator over every user, in order of their user IDs
+    /// unless the producer says otherwise.
+    ///
+    /// The default implementation returns no users, as there’s no way to
+    /// list them in general.
+    fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        Box::new(iter::empty())
+    }
+
+    /// Returns a `User` if one exists for the given user ID, `None` if one
+    /// doesn’t, or an error if the lookup itself failed.
+    ///
+    /// The default implementation can’t tell a failed lookup apart from a
+    /// missing user, and returns `Ok(None)` for both.
+    fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        Ok(self.get_user_by_uid(uid))
+    }
+
+    /// Returns a `User` if one exists for the given username, `None` if one
+    /// doesn’t, or an error if the lookup itself failed.
+    ///
+    /// The default implementation can’t tell a failed lookup apart from a
+    /// missing user, and returns `Ok(None)` for both.
+    fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+        Ok(self.get_user_by_name(username))
+    }
 }
 
 /// Trait for producers of groups.
@@ -48,4 +94,550 @@ pub trait Groups {
 
     /// Returns the effective group name.
     fn get_effective_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Returns the name of the group with the given group ID, if one exists.
+    ///
+    /// This is for callers that only need the name, such as file listers.
+    /// The default implementation looks up the whole group.
+    fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        self.get_group_by_gid(gid).map(|g| Arc::clone(&g.name_arc))
+    }
+
+    /// Returns the users that are members of the group with the given ID,
+    /// either because it’s their primary group or because they’re listed in
+    /// it, in order of their user IDs. Returns `None` if there’s no such
+    /// group, so an empty list always means the group has no members.
+    ///
+    /// Listed members that aren’t users are left out.
+    fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>>;
+
+    /// Returns an iterator over every group, in order of their group IDs
+    /// unless the producer says otherwise.
+    ///
+    /// The default implementation returns no groups, as there’s no way to
+    /// list them in general.
+    fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        Box::new(iter::empty())
+    }
+
+    /// Returns a `Group` if one exists for the given group ID, `None` if one
+    /// doesn’t, or an error if the lookup itself failed.
+    ///
+    /// The default implementation can’t tell a failed lookup apart from a
+    /// missing group, and returns `Ok(None)` for both.
+    fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        Ok(self.get_group_by_gid(gid))
+    }
+
+    /// Returns a `Group` if one exists for the given group name, `None` if
+    /// one doesn’t, or an error if the lookup itself failed.
+    ///
+    /// The default implementation can’t tell a failed lookup apart from a
+    /// missing group, and returns `Ok(None)` for both.
+    fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+        Ok(self.get_group_by_name(group_name))
+    }
+}
+
+
+/// An object-safe version of the [`Users`](trait.Users.html) trait, for
+/// storing a producer of users as a trait object, such as one chosen at
+/// runtime.
+///
+/// Every type that implements `Users` implements this trait too, and trait
+/// objects of it implement `Users` in turn, so a `Box<dyn DynUsers>` can be
+/// used anywhere a `Users` is expected. Its methods are named differently
+/// from the ones in `Users` so that having both traits in scope doesn’t make
+/// calls ambiguous; there should be no need to call them directly.
+///
+/// See [`DynProvider`](trait.DynProvider.html) for a trait object that
+/// produces both users and groups.
+pub trait DynUsers {
+
+    /// Calls [`Users::get_user_by_uid`](trait.Users.html#tymethod.get_user_by_uid).
+    fn dyn_get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>>;
+
+    /// Calls [`Users::get_user_by_name`](trait.Users.html#tymethod.get_user_by_name).
+    fn dyn_get_user_by_name(&self, username: &OsStr) -> Option<Arc<User>>;
+
+    /// Calls [`Users::get_current_uid`](trait.Users.html#tymethod.get_current_uid).
+    fn dyn_get_current_uid(&self) -> uid_t;
+
+    /// Calls [`Users::get_current_username`](trait.Users.html#tymethod.get_current_username).
+    fn dyn_get_current_username(&self) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Users::get_effective_uid`](trait.Users.html#tymethod.get_effective_uid).
+    fn dyn_get_effective_uid(&self) -> uid_t;
+
+    /// Calls [`Users::get_effective_username`](trait.Users.html#tymethod.get_effective_username).
+    fn dyn_get_effective_username(&self) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Users::get_user_name_by_uid`](trait.Users.html#method.get_user_name_by_uid).
+    fn dyn_get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Users::try_get_user_by_uid`](trait.Users.html#method.try_get_user_by_uid).
+    fn dyn_try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>>;
+
+    /// Calls [`Users::try_get_user_by_name`](trait.Users.html#method.try_get_user_by_name).
+    fn dyn_try_get_user_by_name(&self, username: &OsStr) -> io::Result<Option<Arc<User>>>;
+
+    /// Calls [`Users::get_user_groups`](trait.Users.html#method.get_user_groups).
+    fn dyn_get_user_groups(&self, username: &OsStr, gid: gid_t) -> Option<Vec<Arc<Group>>>;
+
+    /// Calls [`Users::all_users`](trait.Users.html#method.all_users).
+    fn dyn_all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_>;
+}
+
+/// An object-safe version of the [`Groups`](trait.Groups.html) trait, which
+/// works the same way as [`DynUsers`](trait.DynUsers.html).
+pub trait DynGroups {
+
+    /// Calls [`Groups::get_group_by_gid`](trait.Groups.html#tymethod.get_group_by_gid).
+    fn dyn_get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>>;
+
+    /// Calls [`Groups::get_group_by_name`](trait.Groups.html#tymethod.get_group_by_name).
+    fn dyn_get_group_by_name(&self, group_name: &OsStr) -> Option<Arc<Group>>;
+
+    /// Calls [`Groups::get_current_gid`](trait.Groups.html#tymethod.get_current_gid).
+    fn dyn_get_current_gid(&self) -> gid_t;
+
+    /// Calls [`Groups::get_current_groupname`](trait.Groups.html#tymethod.get_current_groupname).
+    fn dyn_get_current_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Groups::get_effective_gid`](trait.Groups.html#tymethod.get_effective_gid).
+    fn dyn_get_effective_gid(&self) -> gid_t;
+
+    /// Calls [`Groups::get_effective_groupname`](trait.Groups.html#tymethod.get_effective_groupname).
+    fn dyn_get_effective_groupname(&self) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Groups::get_group_name_by_gid`](trait.Groups.html#method.get_group_name_by_gid).
+    fn dyn_get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>>;
+
+    /// Calls [`Groups::try_get_group_by_gid`](trait.Groups.html#method.try_get_group_by_gid).
+    fn dyn_try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>>;
+
+    /// Calls [`Groups::try_get_group_by_name`](trait.Groups.html#method.try_get_group_by_name).
+    fn dyn_try_get_group_by_name(&self, group_name: &OsStr) -> io::Result<Option<Arc<Group>>>;
+
+    /// Calls [`Groups::get_group_members`](trait.Groups.html#method.get_group_members).
+    fn dyn_get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>>;
+
+    /// Calls [`Groups::all_groups`](trait.Groups.html#method.all_groups).
+    fn dyn_all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_>;
+}
+
+/// An object-safe trait for producers of both users and groups, for
+/// choosing one at runtime.
+///
+/// Every type that implements both `Users` and `Groups` implements this
+/// trait, and trait objects of it implement both of them in turn.
+///
+/// # Examples
+///
+/// ```
+/// use users::{Users, Groups, DynProvider, UsersCache};
+/// use users::mock::{MockUsers, User};
+///
+/// fn provider(use_mock: bool) -> Box<dyn DynProvider + Send + Sync> {
+///     if use_mock {
+///         let mut users = MockUsers::with_current_uid(1000);
+///         users.add_user(User::new(1000, "fred", 1000));
+///         Box::new(users)
+///     }
+///     else {
+///         Box::new(UsersCache::new())
+///     }
+/// }
+///
+/// fn current_name<U: Users>(users: &U) -> String {
+///     users.get_current_username().unwrap().to_string_lossy().into_owned()
+/// }
+///
+/// assert_eq!(current_name(&provider(true)), "fred");
+/// ```
+pub trait DynProvider: DynUsers + DynGroups {}
+
+impl<T: DynUsers + DynGroups> DynProvider for T {}
+
+impl<T: Users> DynUsers for T {
+    fn dyn_get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+        self.get_user_by_uid(uid)
+    }
+
+    fn dyn_get_user_by_name(&self, username: &OsStr) -> Option<Arc<User>> {
+        self.get_user_by_name(username)
+    }
+
+    fn dyn_get_current_uid(&self) -> uid_t {
+        self.get_current_uid()
+    }
+
+    fn dyn_get_current_username(&self) -> Option<Arc<OsStr>> {
+        self.get_current_username()
+    }
+
+    fn dyn_get_effective_uid(&self) -> uid_t {
+        self.get_effective_uid()
+    }
+
+    fn dyn_get_effective_username(&self) -> Option<Arc<OsStr>> {
+        self.get_effective_username()
+    }
+
+    fn dyn_get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+        self.get_user_name_by_uid(uid)
+    }
+
+    fn dyn_try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+        self.try_get_user_by_uid(uid)
+    }
+
+    fn dyn_try_get_user_by_name(&self, username: &OsStr) -> io::Result<Option<Arc<User>>> {
+        self.try_get_user_by_name(username)
+    }
+
+    fn dyn_get_user_groups(&self, username: &OsStr, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+        self.get_user_groups(username, gid)
+    }
+
+    fn dyn_all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+        self.all_users()
+    }
+}
+
+impl<T: Groups> DynGroups for T {
+    fn dyn_get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+        self.get_group_by_gid(gid)
+    }
+
+    fn dyn_get_group_by_name(&self, group_name: &OsStr) -> Option<Arc<Group>> {
+        self.get_group_by_name(group_name)
+    }
+
+    fn dyn_get_current_gid(&self) -> gid_t {
+        self.get_current_gid()
+    }
+
+    fn dyn_get_current_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_current_groupname()
+    }
+
+    fn dyn_get_effective_gid(&self) -> gid_t {
+        self.get_effective_gid()
+    }
+
+    fn dyn_get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+        self.get_effective_groupname()
+    }
+
+    fn dyn_get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+        self.get_group_name_by_gid(gid)
+    }
+
+    fn dyn_try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+        self.try_get_group_by_gid(gid)
+    }
+
+    fn dyn_try_get_group_by_name(&self, group_name: &OsStr) -> io::Result<Option<Arc<Group>>> {
+        self.try_get_group_by_name(group_name)
+    }
+
+    fn dyn_get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+        self.get_group_members(gid)
+    }
+
+    fn dyn_all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+        self.all_groups()
+    }
+}
+
+/// Implements `Users` for trait objects of `DynUsers`, or of a trait that
+/// extends it.
+macro_rules! users_for_dyn {
+    ($($t:ty),*) => {$(
+        impl<'a> Users for $t {
+            fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+                self.dyn_get_user_by_uid(uid)
+            }
+
+            fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+                self.dyn_get_user_by_name(username.as_ref())
+            }
+
+            fn get_current_uid(&self) -> uid_t {
+                self.dyn_get_current_uid()
+            }
+
+            fn get_current_username(&self) -> Option<Arc<OsStr>> {
+                self.dyn_get_current_username()
+            }
+
+            fn get_effective_uid(&self) -> uid_t {
+                self.dyn_get_effective_uid()
+            }
+
+            fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+                self.dyn_get_effective_username()
+            }
+
+            fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+                self.dyn_get_user_name_by_uid(uid)
+            }
+
+            fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+                self.dyn_try_get_user_by_uid(uid)
+            }
+
+            fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+                self.dyn_try_get_user_by_name(username.as_ref())
+            }
+
+            fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+                self.dyn_get_user_groups(username.as_ref(), gid)
+            }
+
+            fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+                self.dyn_all_users()
+            }
+        }
+    )*};
+}
+
+/// Implements `Groups` for trait objects of `DynGroups`, or of a trait that
+/// extends it.
+macro_rules! groups_for_dyn {
+    ($($t:ty),*) => {$(
+        impl<'a> Groups for $t {
+            fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+                self.dyn_get_group_by_gid(gid)
+            }
+
+            fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+                self.dyn_get_group_by_name(group_name.as_ref())
+            }
+
+            fn get_current_gid(&self) -> gid_t {
+                self.dyn_get_current_gid()
+            }
+
+            fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+                self.dyn_get_current_groupname()
+            }
+
+            fn get_effective_gid(&self) -> gid_t {
+                self.dyn_get_effective_gid()
+            }
+
+            fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+                self.dyn_get_effective_groupname()
+            }
+
+            fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+                self.dyn_get_group_name_by_gid(gid)
+            }
+
+            fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+                self.dyn_try_get_group_by_gid(gid)
+            }
+
+            fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+                self.dyn_try_get_group_by_name(group_name.as_ref())
+            }
+
+            fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+                self.dyn_get_group_members(gid)
+            }
+
+            fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+                self.dyn_all_groups()
+            }
+        }
+    )*};
+}
+
+users_for_dyn!(dyn DynUsers + 'a, dyn DynUsers + Send + Sync + 'a,
+               dyn DynProvider + 'a, dyn DynProvider + Send + Sync + 'a);
+
+groups_for_dyn!(dyn DynGroups + 'a, dyn DynGroups + Send + Sync + 'a,
+                dyn DynProvider + 'a, dyn DynProvider + Send + Sync + 'a);
+
+/// Implements `Users` for pointers to types that implement it.
+macro_rules! users_for_pointer {
+    ($([$($params:tt)*] $t:ty),*) => {$(
+        impl<$($params)*> Users for $t {
+            fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+                (**self).get_user_by_uid(uid)
+            }
+
+            fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+                (**self).get_user_by_name(username)
+            }
+
+            fn get_current_uid(&self) -> uid_t {
+                (**self).get_current_uid()
+            }
+
+            fn get_current_username(&self) -> Option<Arc<OsStr>> {
+                (**self).get_current_username()
+            }
+
+            fn get_effective_uid(&self) -> uid_t {
+                (**self).get_effective_uid()
+            }
+
+            fn get_effective_username(&self) -> Option<Arc<OsStr>> {
+                (**self).get_effective_username()
+            }
+
+            fn get_user_name_by_uid(&self, uid: uid_t) -> Option<Arc<OsStr>> {
+                (**self).get_user_name_by_uid(uid)
+            }
+
+            fn try_get_user_by_uid(&self, uid: uid_t) -> io::Result<Option<Arc<User>>> {
+                (**self).try_get_user_by_uid(uid)
+            }
+
+            fn try_get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> io::Result<Option<Arc<User>>> {
+                (**self).try_get_user_by_name(username)
+            }
+
+            fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+                (**self).get_user_groups(username, gid)
+            }
+
+            fn all_users(&self) -> Box<dyn Iterator<Item=Arc<User>> + '_> {
+                (**self).all_users()
+            }
+        }
+    )*};
+}
+
+/// Implements `Groups` for pointers to types that implement it.
+macro_rules! groups_for_pointer {
+    ($([$($params:tt)*] $t:ty),*) => {$(
+        impl<$($params)*> Groups for $t {
+            fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+                (**self).get_group_by_gid(gid)
+            }
+
+            fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+                (**self).get_group_by_name(group_name)
+            }
+
+            fn get_current_gid(&self) -> gid_t {
+                (**self).get_current_gid()
+            }
+
+            fn get_current_groupname(&self) -> Option<Arc<OsStr>> {
+                (**self).get_current_groupname()
+            }
+
+            fn get_effective_gid(&self) -> gid_t {
+                (**self).get_effective_gid()
+            }
+
+            fn get_effective_groupname(&self) -> Option<Arc<OsStr>> {
+                (**self).get_effective_groupname()
+            }
+
+            fn get_group_name_by_gid(&self, gid: gid_t) -> Option<Arc<OsStr>> {
+                (**self).get_group_name_by_gid(gid)
+            }
+
+            fn try_get_group_by_gid(&self, gid: gid_t) -> io::Result<Option<Arc<Group>>> {
+                (**self).try_get_group_by_gid(gid)
+            }
+
+            fn try_get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> io::Result<Option<Arc<Group>>> {
+                (**self).try_get_group_by_name(group_name)
+            }
+
+            fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+                (**self).get_group_members(gid)
+            }
+
+            fn all_groups(&self) -> Box<dyn Iterator<Item=Arc<Group>> + '_> {
+                (**self).all_groups()
+            }
+        }
+    )*};
+}
+
+users_for_pointer!(['a, T: Users + ?Sized] &'a T, [T: Users + ?Sized] Box<T>, [T: Users + ?Sized] Arc<T>);
+groups_for_pointer!(['a, T: Groups + ?Sized] &'a T, [T: Groups + ?Sized] Box<T>, [T: Groups + ?Sized] Arc<T>);
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::OsString;
+
+    struct Fixed;
+
+    impl Users for Fixed {
+        fn get_user_by_uid(&self, uid: uid_t) -> Option<Arc<User>> {
+            if uid == 1000 { Some(Arc::new(User::new(1000, "fred", 100))) } else { None }
+        }
+
+        fn get_user_by_name<S: AsRef<OsStr> + ?Sized>(&self, username: &S) -> Option<Arc<User>> {
+            if username.as_ref() == "fred" { self.get_user_by_uid(1000) } else { None }
+        }
+
+        fn get_current_uid(&self) -> uid_t { 1000 }
+        fn get_current_username(&self) -> Option<Arc<OsStr>> { Some(Arc::from(OsStr::new("fred"))) }
+        fn get_effective_uid(&self) -> uid_t { 0 }
+        fn get_effective_username(&self) -> Option<Arc<OsStr>> { None }
+
+        fn get_user_groups<S: AsRef<OsStr> + ?Sized>(&self, username: &S, gid: gid_t) -> Option<Vec<Arc<Group>>> {
+            if username.as_ref() == "fred" { self.get_group_by_gid(gid).map(|g| vec![ g ]) } else { None }
+        }
+    }
+
+    impl Groups for Fixed {
+        fn get_group_by_gid(&self, gid: gid_t) -> Option<Arc<Group>> {
+            if gid == 100 { Some(Arc::new(Group::new(100, "staff"))) } else { None }
+        }
+
+        fn get_group_by_name<S: AsRef<OsStr> + ?Sized>(&self, group_name: &S) -> Option<Arc<Group>> {
+            if group_name.as_ref() == "staff" { self.get_group_by_gid(100) } else { None }
+        }
+
+        fn get_current_gid(&self) -> gid_t { 100 }
+        fn get_current_groupname(&self) -> Option<Arc<OsStr>> { Some(Arc::from(OsStr::new("staff"))) }
+        fn get_effective_gid(&self) -> gid_t { 0 }
+        fn get_effective_groupname(&self) -> Option<Arc<OsStr>> { None }
+
+        fn get_group_members(&self, gid: gid_t) -> Option<Vec<Arc<User>>> {
+            if gid == 100 { Some(vec![ self.get_user_by_uid(1000)? ]) } else { None }
+        }
+    }
+
+    fn names<P: Users + Groups + ?Sized>(provider: &P) -> (Option<OsString>, Option<OsString>) {
+        let user = provider.get_user_by_name("fred").map(|u| u.name().to_os_string());
+        let group = provider.get_group_by_name(&OsString::from("staff")).map(|g| g.name().to_os_string());
+        (user, group)
+    }
+
+    #[test]
+    fn boxed_provider() {
+        let provider: Box<dyn DynProvider> = Box::new(Fixed);
+        assert_eq!(names(&provider), (Some("fred".into()), Some("staff".into())));
+        assert_eq!(names(&*provider), (Some("fred".into()), Some("staff".into())));
+        assert_eq!(provider.get_current_uid(), 1000);
+        assert_eq!(provider.get_effective_gid(), 0);
+        assert!(provider.try_get_user_by_uid(1001).unwrap().is_none());
+        assert_eq!(provider.get_user_groups("fred", 100).unwrap().len(), 1);
+        assert!(provider.get_group_members(101).is_none());
+    }
+
+    #[test]
+    fn shared_provider() {
+        let provider: Arc<dyn DynProvider + Send + Sync> = Arc::new(Fixed);
+        let users: &dyn DynUsers = &Fixed;
+        let groups: &dyn DynGroups = &Fixed;
+
+        assert_eq!(names(&provider), (Some("fred".into()), Some("staff".into())));
+        assert_eq!(users.get_user_name_by_uid(1000), Some(Arc::from(OsStr::new("fred"))));
+        assert_eq!(groups.get_group_name_by_gid(100), Some(Arc::from(OsStr::new("staff"))));
+    }
 }